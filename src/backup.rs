@@ -0,0 +1,156 @@
+use anyhow::{anyhow, Result, Context};
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::history::History;
+use crate::svt;
+use crate::ui::AppOptions;
+
+//backups of a map taken before every write, named <map>.<utc timestamp>.osu
+//they go in .svt/backups/ beside the map unless a backup folder is set, and old ones are pruned by count and age
+//the newest backup is always kept
+
+const BACKUP_FOLDER: &str = ".svt/backups";
+
+pub struct Backup {
+  pub path: PathBuf,
+  //seconds since the unix epoch, from the file name
+  pub time: u64,
+}
+
+//create a new backup of the map and prune old ones
+pub fn create_backup(map: &str, opt: &AppOptions) -> Result<PathBuf> {
+  let (folder, stem) = backup_location(map, opt)?;
+  fs::create_dir_all(&folder).context("[backup] couldn't create backup folder")?;
+
+  let now = SystemTime::now().duration_since(UNIX_EPOCH).context("[backup] invalid system time")?;
+  let mut path = folder.join(format!("{}.{}.osu", stem, format_utc(now.as_secs())));
+  //backups within the same second get a counter
+  let mut i = 1;
+  while path.exists() {
+    path = folder.join(format!("{}.{}-{}.osu", stem, format_utc(now.as_secs()), i));
+    i += 1;
+  }
+  fs::copy(map, &path).with_context(|| format!("[backup] error backing up file {}", map))?;
+
+  //pruning failures shouldn't stop a write that is already backed up
+  let backups = list_backups(map, opt).unwrap_or_default();
+  for (i, backup) in backups.iter().enumerate() {
+    if i == 0 {
+      continue;
+    }
+    let too_many = opt.backup_count > 0 && i >= opt.backup_count as usize;
+    let too_old = opt.backup_days > 0 && now.as_secs().saturating_sub(backup.time) > opt.backup_days as u64 * 86400;
+    if too_many || too_old {
+      eprintln!("[backup] removing {}", backup.path.display());
+      let _ = fs::remove_file(&backup.path);
+    }
+  }
+
+  Ok(path)
+}
+
+//backups of a map, newest first
+pub fn list_backups(map: &str, opt: &AppOptions) -> Result<Vec<Backup>> {
+  let (folder, stem) = backup_location(map, opt)?;
+  let mut backups = Vec::new();
+
+  let entries = match fs::read_dir(&folder) {
+    Ok(entries) => entries,
+    Err(_) => return Ok(Vec::new()),
+  };
+  for entry in entries {
+    let path = entry.context("[backup] couldn't read backup folder")?.path();
+    let name = path.file_name().and_then(|n| n.to_str()).unwrap_or("");
+
+    //<stem>.<timestamp>[-<i>].osu, other maps can share the folder
+    let timestamp = name.strip_prefix(&format!("{}.", stem)).and_then(|n| n.strip_suffix(".osu")).unwrap_or("");
+    let mut parts = timestamp.splitn(2, "-");
+    let time = parse_utc(parts.next().unwrap_or(""));
+    let counter = parts.next().map_or(Some(0), |i| i.parse::<u32>().ok());
+    if let (Some(time), Some(counter)) = (time, counter) {
+      backups.push((counter, Backup{path: path.clone(), time: time}));
+    }
+  }
+
+  backups.sort_by(|(a_counter, a), (b_counter, b)| b.time.cmp(&a.time).then(b_counter.cmp(a_counter)));
+  Ok(backups.into_iter().map(|(_, backup)| backup).collect())
+}
+
+//replace the map with a backup, backing up the map as it is first so the restore can be reverted
+//the restore is written like any other change and can be undone from the history
+pub fn restore_backup(map: &str, backup: &Path, opt: &AppOptions) -> Result<()> {
+  if !backup.exists() {
+    return Err(anyhow!("[backup] {} doesn't exist", backup.display()));
+  }
+  let backup_copy = fs::read_to_string(backup).context("[backup] couldn't read backup")?;
+
+  let mut history = History::load_or_reset(map)?;
+  history.checkpoint()?;
+  create_backup(map, opt)?;
+  svt::write_map(map, &backup_copy).context("[backup] couldn't restore backup")?;
+
+  let backup_name = backup.file_name().and_then(|name| name.to_str()).unwrap_or("backup");
+  history.record(&format!("restore {}", backup_name))
+}
+
+//backup folder and the map name backups start with
+fn backup_location(map: &str, opt: &AppOptions) -> Result<(PathBuf, String)> {
+  let map_path = Path::new(map);
+  let stem = map_path.file_stem().and_then(|s| s.to_str()).ok_or(anyhow!("[backup] invalid map path {}", map))?;
+
+  let folder = if opt.backup_folder.trim().len() > 0 {
+    PathBuf::from(opt.backup_folder.trim())
+  } else {
+    map_path.parent().unwrap_or(Path::new("")).join(BACKUP_FOLDER)
+  };
+
+  Ok((folder, stem.to_string()))
+}
+
+//yyyymmddThhmmssZ
+fn format_utc(secs: u64) -> String {
+  let days = (secs / 86400) as i64;
+  let rem = secs % 86400;
+  let (y, m, d) = civil_from_days(days);
+  format!("{:04}{:02}{:02}T{:02}{:02}{:02}Z", y, m, d, rem / 3600, rem / 60 % 60, rem % 60)
+}
+
+fn parse_utc(s: &str) -> Option<u64> {
+  if s.len() != 16 || !s.is_ascii() || &s[8..9] != "T" || &s[15..16] != "Z" {
+    return None;
+  }
+  let num = |a: usize, b: usize| s[a..b].parse::<u64>().ok();
+  let days = days_from_civil(num(0, 4)? as i64, num(4, 6)?, num(6, 8)?);
+  if days < 0 {
+    return None;
+  }
+  Some(days as u64 * 86400 + num(9, 11)? * 3600 + num(11, 13)? * 60 + num(13, 15)?)
+}
+
+//gregorian date of a day count since 1970-01-01
+fn civil_from_days(days: i64) -> (i64, u64, u64) {
+  let z = days + 719468;
+  let era = z.div_euclid(146097);
+  let doe = z.rem_euclid(146097);
+  let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+  let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+  let mp = (5 * doy + 2) / 153;
+  let d = (doy - (153 * mp + 2) / 5 + 1) as u64;
+  let m = if mp < 10 { mp + 3 } else { mp - 9 } as u64;
+  let y = yoe + era * 400 + if m <= 2 { 1 } else { 0 };
+  (y, m, d)
+}
+
+//day count since 1970-01-01 of a gregorian date
+fn days_from_civil(y: i64, m: u64, d: u64) -> i64 {
+  let y = if m <= 2 { y - 1 } else { y };
+  let era = y.div_euclid(400);
+  let yoe = y.rem_euclid(400);
+  let mp = if m > 2 { m - 3 } else { m + 9 } as i64;
+  let doy = (153 * mp + 2) / 5 + d as i64 - 1;
+  let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+  era * 146097 + doe - 719468
+}
@@ -0,0 +1,146 @@
+use anyhow::{anyhow, Result, Context};
+
+use std::fs;
+use std::path::PathBuf;
+
+use crate::backup;
+use crate::diff;
+use crate::preview;
+use crate::svt::SVT;
+use crate::ui::{self, AppOptions};
+
+//command line tools, using the options saved by the ui
+//  svt --dry-run [--unified] [<map.osu>] - print the timing point changes the saved input would make without writing,
+//    or with --unified a diff of the whole map that can be applied with patch
+//  svt --backups [<map.osu>] - list the map's backups, newest first
+//  svt --restore <n|backup.osu> [<map.osu>] - restore the nth backup from the list (or a backup file)
+//  svt --clean-previews [<map.osu>] - remove the preview difficulties svt wrote in the map's folder
+//the map defaults to the one last opened in the ui
+//logging from loading and applying goes to stderr, only the command's own output is printed to stdout
+pub fn run(args: &[String]) -> Result<()> {
+  let mut app_options = ui::read_options();
+
+  match args[0].as_str() {
+    "--dry-run" => {
+      let mut unified = false;
+      for arg in args.iter().skip(1) {
+        match arg.as_str() {
+          "--unified" => unified = true,
+          _ if arg.starts_with("--") => return Err(anyhow!("[cli] unknown option {}", arg)),
+          _ => app_options.map = arg.clone(),
+        }
+      }
+      dry_run(&app_options, unified)
+    },
+    "--backups" => {
+      if let Some(map) = args.get(1) {
+        app_options.map = map.clone();
+      }
+      list_backups(&app_options)
+    },
+    "--restore" => {
+      let backup = args.get(1).ok_or(anyhow!("[cli] no backup given"))?;
+      if let Some(map) = args.get(2) {
+        app_options.map = map.clone();
+      }
+      restore_backup(&app_options, backup)
+    },
+    "--clean-previews" => {
+      if let Some(map) = args.get(1) {
+        app_options.map = map.clone();
+      }
+      clean_previews(&app_options)
+    },
+    _ => Err(anyhow!("[cli] unknown command {}", args[0])),
+  }
+}
+
+fn dry_run(app_options: &AppOptions, unified: bool) -> Result<()> {
+  if app_options.map.len() == 0 {
+    return Err(anyhow!("[cli] no map given"));
+  }
+
+  let mut svt = SVT::default();
+  svt.load_osu(&app_options.map, app_options)?;
+  if svt.skipped_lines > 0 {
+    eprintln!("[cli] {} malformed lines skipped", svt.skipped_lines);
+  }
+
+  let ranges = svt.parse_input(&app_options.inh_times)?;
+  for range in ranges.iter() {
+    svt.apply_two_point_fn(range, app_options)?;
+  }
+
+  let (diffs, out_string) = svt.diff_output_points(app_options.min_spacing.clone(), app_options.merge_policy.clone(), app_options.fix_sliders, app_options)?;
+  if unified {
+    let map_string = fs::read_to_string(&app_options.map).context("[cli] couldn't read map")?;
+    print!("{}", diff::format_unified(&map_string, &out_string, &app_options.map));
+  } else {
+    print!("{}", diff::format_table(&diffs));
+  }
+
+  //what merging did follows the table, a patch has to stay applicable so it goes to stderr there
+  let mut report = svt.apply_report(app_options.fix_sliders);
+  if app_options.optimize {
+    report.push(format!("{} lines saved by optimizing", svt.optimized_lines));
+  }
+  for line in report.iter() {
+    if unified {
+      eprintln!("{}", line);
+    } else {
+      println!("{}", line);
+    }
+  }
+
+  Ok(())
+}
+
+fn list_backups(app_options: &AppOptions) -> Result<()> {
+  if app_options.map.len() == 0 {
+    return Err(anyhow!("[cli] no map given"));
+  }
+
+  let backups = backup::list_backups(&app_options.map, app_options)?;
+  if backups.len() == 0 {
+    println!("no backups of {}", app_options.map);
+  }
+  for (i, backup) in backups.iter().enumerate() {
+    println!("{:>3}  {}", i + 1, backup.path.display());
+  }
+
+  Ok(())
+}
+
+fn restore_backup(app_options: &AppOptions, backup: &str) -> Result<()> {
+  if app_options.map.len() == 0 {
+    return Err(anyhow!("[cli] no map given"));
+  }
+
+  //a number picks from the list, newest first
+  let path = match backup.parse::<usize>() {
+    Ok(n) => {
+      let backups = backup::list_backups(&app_options.map, app_options)?;
+      backups.into_iter().nth(n.wrapping_sub(1)).map(|b| b.path).ok_or(anyhow!("[cli] no backup {}", n))?
+    },
+    Err(_) => PathBuf::from(backup),
+  };
+
+  backup::restore_backup(&app_options.map, &path, app_options)?;
+  println!("restored {} from {}", app_options.map, path.display());
+
+  Ok(())
+}
+
+fn clean_previews(app_options: &AppOptions) -> Result<()> {
+  if app_options.map.len() == 0 {
+    return Err(anyhow!("[cli] no map given"));
+  }
+
+  let removed = preview::clean_previews(&app_options.map)?;
+  for path in removed.iter() {
+    println!("removed {}", path.display());
+  }
+  println!("{} previews removed", removed.len());
+
+  Ok(())
+}
@@ -0,0 +1,238 @@
+use crate::svt::{self, MapObject};
+
+//timing point changes between the current map and the output of an operation, for dry runs
+//points are matched by time and red/green, a matched point with different values is modified
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DiffKind {
+  Added,
+  Removed,
+  Modified,
+}
+
+#[derive(Clone, Debug)]
+pub struct TimingDiff {
+  pub kind: DiffKind,
+  pub time: i32,
+  pub old: Option<MapObject>,
+  pub new: Option<MapObject>,
+}
+
+//both lists should be sorted chronologically with red lines before green lines at the same time
+pub fn diff_timing_points(old_points: &[MapObject], new_points: &[MapObject]) -> Vec<TimingDiff> {
+  let mut diffs = Vec::new();
+  let mut i = 0;
+  let mut j = 0;
+
+  while i < old_points.len() || j < new_points.len() {
+    let old_key = old_points.get(i).map(|obj| (obj.time, obj.uninherited ^ 1));
+    let new_key = new_points.get(j).map(|obj| (obj.time, obj.uninherited ^ 1));
+
+    let kind = match (old_key, new_key) {
+      (Some(old_key), Some(new_key)) if old_key == new_key => {
+        if old_points[i].data == new_points[j].data {
+          i += 1;
+          j += 1;
+          continue;
+        }
+        DiffKind::Modified
+      },
+      (Some(old_key), Some(new_key)) if old_key < new_key => DiffKind::Removed,
+      (Some(_), None) => DiffKind::Removed,
+      _ => DiffKind::Added,
+    };
+
+    let old = if kind != DiffKind::Added { Some(old_points[i].clone()) } else { None };
+    let new = if kind != DiffKind::Removed { Some(new_points[j].clone()) } else { None };
+    let time = old.as_ref().or(new.as_ref()).unwrap().time;
+    diffs.push(TimingDiff{kind: kind, time: time, old: old, new: new});
+
+    if kind != DiffKind::Added {
+      i += 1;
+    }
+    if kind != DiffKind::Removed {
+      j += 1;
+    }
+  }
+
+  diffs
+}
+
+//one row per change with the old and new sv/volume
+pub fn format_table(diffs: &[TimingDiff]) -> String {
+  let mut out_string = format!("{:<12}{:<10}{:<20}{}\n", "time", "change", "old", "new");
+  for diff in diffs.iter() {
+    let kind = match diff.kind {
+      DiffKind::Added => "added",
+      DiffKind::Removed => "removed",
+      DiffKind::Modified => "modified",
+    };
+    let old = diff.old.as_ref().map(describe_point).unwrap_or(String::from("-"));
+    let new = diff.new.as_ref().map(describe_point).unwrap_or(String::from("-"));
+    out_string += &format!("{:<12}{:<10}{:<20}{}\n", svt::format_timestamp(diff.time), kind, old, new);
+  }
+  out_string
+}
+
+//lines of context around each hunk, as in diff -u
+const CONTEXT_LINES: usize = 3;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Edit {
+  Same,
+  Remove,
+  Add,
+}
+
+//unified diff between the map as it is and as it would be written, with file line numbers so it can be applied with patch
+//lines keep their line endings, so crlf maps get crlf patch lines
+pub fn format_unified(old_contents: &str, new_contents: &str, filename: &str) -> String {
+  let old_lines: Vec<&str> = old_contents.split_inclusive("\n").collect();
+  let new_lines: Vec<&str> = new_contents.split_inclusive("\n").collect();
+  let script = edit_script(&old_lines, &new_lines);
+
+  let mut out_string = String::new();
+  if script.iter().all(|(edit, _, _)| *edit == Edit::Same) {
+    return out_string;
+  }
+  out_string += &format!("--- {}\n+++ {}\n", filename, filename);
+
+  let mut idx = 0;
+  while let Some(first_change) = script[idx..].iter().position(|(edit, _, _)| *edit != Edit::Same).map(|i| i + idx) {
+    //a hunk runs until more than twice the context of unchanged lines separates it from the next change
+    let start = first_change.saturating_sub(CONTEXT_LINES);
+    let mut end = first_change;
+    let mut same_run = 0;
+    for (i, (edit, _, _)) in script.iter().enumerate().skip(first_change) {
+      if *edit == Edit::Same {
+        same_run += 1;
+        if same_run > 2 * CONTEXT_LINES {
+          break;
+        }
+      } else {
+        same_run = 0;
+        end = i + 1;
+      }
+    }
+    let end = usize::min(end + CONTEXT_LINES, script.len());
+    let hunk = &script[start..end];
+
+    let old_count = hunk.iter().filter(|(edit, _, _)| *edit != Edit::Add).count();
+    let new_count = hunk.iter().filter(|(edit, _, _)| *edit != Edit::Remove).count();
+    //an empty side starts at the line before, as in diff -u
+    let old_start = if old_count == 0 { hunk[0].1 } else { hunk[0].1 + 1 };
+    let new_start = if new_count == 0 { hunk[0].2 } else { hunk[0].2 + 1 };
+    out_string += &format!("@@ -{},{} +{},{} @@\n", old_start, old_count, new_start, new_count);
+
+    for (edit, old_i, new_i) in hunk.iter() {
+      let (prefix, line) = match edit {
+        Edit::Same => (" ", old_lines[*old_i]),
+        Edit::Remove => ("-", old_lines[*old_i]),
+        Edit::Add => ("+", new_lines[*new_i]),
+      };
+      out_string += prefix;
+      out_string += line;
+      if !line.ends_with("\n") {
+        out_string += "\n\\ No newline at end of file\n";
+      }
+    }
+
+    idx = end;
+  }
+
+  out_string
+}
+
+//shortest edit script between two lists of lines (myers' algorithm), each step with the old and new line index it's at
+//uses the linear space variant, splitting on the middle snake, so maps with thousands of changed lines don't need a path per edit
+fn edit_script(old_lines: &[&str], new_lines: &[&str]) -> Vec<(Edit, usize, usize)> {
+  let mut script = Vec::new();
+  diff_between(old_lines, new_lines, 0, 0, &mut script);
+  script
+}
+
+//edit script for old_lines/new_lines starting at old_start/new_start in the whole lists, added to script in order
+fn diff_between(old_lines: &[&str], new_lines: &[&str], old_start: usize, new_start: usize, script: &mut Vec<(Edit, usize, usize)>) {
+  //lines the maps start and end with are the same almost everywhere, only the middle needs searching
+  let prefix = old_lines.iter().zip(new_lines.iter()).take_while(|(a, b)| a == b).count();
+  let suffix = old_lines[prefix..].iter().rev().zip(new_lines[prefix..].iter().rev()).take_while(|(a, b)| a == b).count();
+  let old_mid = &old_lines[prefix..old_lines.len() - suffix];
+  let new_mid = &new_lines[prefix..new_lines.len() - suffix];
+  let (old_mid_start, new_mid_start) = (old_start + prefix, new_start + prefix);
+
+  script.extend((0..prefix).map(|i| (Edit::Same, old_start + i, new_start + i)));
+  if old_mid.len() == 0 {
+    script.extend((0..new_mid.len()).map(|i| (Edit::Add, old_mid_start, new_mid_start + i)));
+  } else if new_mid.len() == 0 {
+    script.extend((0..old_mid.len()).map(|i| (Edit::Remove, old_mid_start + i, new_mid_start)));
+  } else {
+    //both ends differ here, so there are at least 2 edits and each half has fewer
+    let (x, y, u, v) = middle_snake(old_mid, new_mid);
+    diff_between(&old_mid[..x], &new_mid[..y], old_mid_start, new_mid_start, script);
+    script.extend((0..u - x).map(|i| (Edit::Same, old_mid_start + x + i, new_mid_start + y + i)));
+    diff_between(&old_mid[u..], &new_mid[v..], old_mid_start + u, new_mid_start + v, script);
+  }
+  let (old_end, new_end) = (old_start + old_lines.len() - suffix, new_start + new_lines.len() - suffix);
+  script.extend((0..suffix).map(|i| (Edit::Same, old_end + i, new_end + i)));
+}
+
+//the snake (x, y) to (u, v) in the middle of a shortest edit path, found by searching from both ends until the paths overlap
+//forward paths keep the furthest x on each diagonal k = x - y, backward paths the furthest distance from the end on each diagonal c
+fn middle_snake(old_lines: &[&str], new_lines: &[&str]) -> (usize, usize, usize, usize) {
+  let n = old_lines.len() as isize;
+  let m = new_lines.len() as isize;
+  let delta = n - m;
+  let odd = delta % 2 != 0;
+  let max_d = (n + m + 1) / 2;
+  let offset = max_d + 1;
+  let mut forward = vec![0isize; (2 * offset + 1) as usize];
+  let mut backward = vec![0isize; (2 * offset + 1) as usize];
+
+  for d in 0..=max_d {
+    for k in (-d..=d).step_by(2) {
+      let i = (k + offset) as usize;
+      let mut x = if k == -d || (k != d && forward[i - 1] < forward[i + 1]) { forward[i + 1] } else { forward[i - 1] + 1 };
+      let (start_x, start_y) = (x, x - k);
+      let mut y = x - k;
+      while x < n && y < m && old_lines[x as usize] == new_lines[y as usize] {
+        x += 1;
+        y += 1;
+      }
+      forward[i] = x;
+
+      //with an odd delta the paths first meet going forwards, against the backward paths from the step before
+      let c = delta - k;
+      if odd && c >= -(d - 1) && c <= d - 1 && x + backward[(c + offset) as usize] >= n {
+        return (start_x as usize, start_y as usize, x as usize, y as usize);
+      }
+    }
+
+    for c in (-d..=d).step_by(2) {
+      let i = (c + offset) as usize;
+      let mut x = if c == -d || (c != d && backward[i - 1] < backward[i + 1]) { backward[i + 1] } else { backward[i - 1] + 1 };
+      let (start_x, start_y) = (x, x - c);
+      let mut y = x - c;
+      while x < n && y < m && old_lines[(n - x - 1) as usize] == new_lines[(m - y - 1) as usize] {
+        x += 1;
+        y += 1;
+      }
+      backward[i] = x;
+
+      let k = delta - c;
+      if !odd && k >= -d && k <= d && forward[(k + offset) as usize] + x >= n {
+        return ((n - x) as usize, (m - y) as usize, (n - start_x) as usize, (m - start_y) as usize);
+      }
+    }
+  }
+
+  unreachable!("[diff] paths always meet within (n + m) / 2 steps")
+}
+
+//bpm or sv and volume of a timing point, e.g. 180bpm 60% or 1.25x 60%
+fn describe_point(obj: &MapObject) -> String {
+  if obj.uninherited == 1 {
+    format!("{:.2}bpm {}%", 60000.0 / obj.beatlength, obj.volume)
+  } else {
+    format!("{:.2}x {}%", -100.0 / obj.beatlength, obj.volume)
+  }
+}
@@ -0,0 +1,128 @@
+use anyhow::{anyhow, Result};
+
+use std::cmp;
+
+use crate::svt::MapObject;
+
+//hit object filters, written as space separated terms that all have to match
+//alternatives can be combined with | and any term can be negated with a leading !
+//  circle slider spinner hold - object type
+//  whistle finish clap plain - hitsound additions (plain has none)
+//  don kat big - taiko notes
+//  nc - new combo starts
+//  col<N> - mania column, counting from 1
+//  normal soft drum - sampleset of the object
+//  custom custom<N> - custom sample index (any, or a specific one)
+//for example "finish !big", "col1|col4" or "slider nc"
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum HitTerm {
+  Circle,
+  Slider,
+  Spinner,
+  Hold,
+  Whistle,
+  Finish,
+  Clap,
+  Plain,
+  Don,
+  Kat,
+  Big,
+  NewCombo,
+  Column(i32),
+  SampleSet(i32),
+  Custom(Option<i32>),
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct HitFilter {
+  //every clause has to match, a clause matches if any of its (negated, term) alternatives match
+  clauses: Vec<Vec<(bool, HitTerm)>>,
+}
+
+impl HitFilter {
+  pub fn parse(text: &str) -> Result<HitFilter> {
+    let mut clauses = Vec::new();
+
+    for clause_str in text.split_whitespace() {
+      let mut clause = Vec::new();
+      for alt in clause_str.split("|") {
+        let (negated, term_str) = match alt.strip_prefix("!") {
+          Some(term_str) => (true, term_str),
+          None => (false, alt),
+        };
+        clause.push((negated, parse_term(term_str)?));
+      }
+      clauses.push(clause);
+    }
+
+    Ok(HitFilter{clauses: clauses})
+  }
+
+  //keys is the mania key count (CircleSize), only used for column terms
+  pub fn matches(&self, obj: &MapObject, keys: i32) -> bool {
+    self.clauses.iter().all(|clause| {
+      clause.iter().any(|(negated, term)| term_matches(*term, obj, keys) != *negated)
+    })
+  }
+}
+
+pub fn parse_term(term: &str) -> Result<HitTerm> {
+  let hit_term = match term {
+    "circle" => HitTerm::Circle,
+    "slider" => HitTerm::Slider,
+    "spinner" => HitTerm::Spinner,
+    "hold" => HitTerm::Hold,
+    "whistle" => HitTerm::Whistle,
+    "finish" => HitTerm::Finish,
+    "clap" => HitTerm::Clap,
+    "plain" => HitTerm::Plain,
+    "don" => HitTerm::Don,
+    "kat" => HitTerm::Kat,
+    "big" => HitTerm::Big,
+    "nc" => HitTerm::NewCombo,
+    "normal" => HitTerm::SampleSet(1),
+    "soft" => HitTerm::SampleSet(2),
+    "drum" => HitTerm::SampleSet(3),
+    "custom" => HitTerm::Custom(None),
+    _ => {
+      if let Some(col) = term.strip_prefix("col") {
+        match col.parse::<i32>() {
+          Ok(col) if col > 0 => HitTerm::Column(col),
+          _ => return Err(anyhow!("[filter] invalid column {}", term)),
+        }
+      } else if let Some(idx) = term.strip_prefix("custom") {
+        match idx.parse::<i32>() {
+          Ok(idx) if idx > 0 => HitTerm::Custom(Some(idx)),
+          _ => return Err(anyhow!("[filter] invalid custom index {}", term)),
+        }
+      } else {
+        return Err(anyhow!("[filter] unknown term {}", term));
+      }
+    },
+  };
+
+  Ok(hit_term)
+}
+
+pub fn term_matches(term: HitTerm, obj: &MapObject, keys: i32) -> bool {
+  match term {
+    HitTerm::Circle => obj.hit_type & 1 != 0,
+    HitTerm::Slider => obj.hit_type & 2 != 0,
+    HitTerm::Spinner => obj.hit_type & 8 != 0,
+    HitTerm::Hold => obj.hit_type & 128 != 0,
+    HitTerm::Whistle => obj.hitsound & 2 != 0,
+    HitTerm::Finish => obj.hitsound & 4 != 0,
+    HitTerm::Clap => obj.hitsound & 8 != 0,
+    HitTerm::Plain => obj.hitsound & 14 == 0,
+    //taiko kats are whistles or claps, big notes are finishes
+    HitTerm::Don => obj.hit_type & 1 != 0 && obj.hitsound & 10 == 0,
+    HitTerm::Kat => obj.hit_type & 1 != 0 && obj.hitsound & 10 != 0,
+    HitTerm::Big => obj.hitsound & 4 != 0,
+    HitTerm::NewCombo => obj.hit_type & 4 != 0,
+    HitTerm::Column(col) => keys > 0 && cmp::min(obj.x * keys / 512, keys - 1) == col - 1,
+    HitTerm::SampleSet(set) => obj.sampleset == set,
+    HitTerm::Custom(None) => obj.sampleindex > 0,
+    HitTerm::Custom(Some(idx)) => obj.sampleindex == idx,
+  }
+}
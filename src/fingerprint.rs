@@ -0,0 +1,121 @@
+use anyhow::{Result, Context};
+
+use std::collections::HashMap;
+use std::fs;
+use std::time::SystemTime;
+
+use crate::diff;
+use crate::osufile::OsuFile;
+use crate::svt::{self, MapObject};
+
+//what a map looked like on disk when svt last loaded or wrote it, to notice the editor saving it in between
+//size and modification time are checked first, the content hash decides when they differ (e.g. a save without changes)
+
+#[derive(Clone, Debug)]
+pub struct MapFingerprint {
+  pub path: String,
+  pub len: u64,
+  pub modified: Option<SystemTime>,
+  pub hash: u64,
+  contents: String,
+}
+
+impl MapFingerprint {
+  pub fn read(path: &str) -> Result<MapFingerprint> {
+    let metadata = fs::metadata(path).with_context(|| format!("[check] couldn't read {}", path))?;
+    let bytes = fs::read(path).with_context(|| format!("[check] couldn't read {}", path))?;
+
+    Ok(MapFingerprint{
+      path: path.to_string(),
+      len: metadata.len(),
+      modified: metadata.modified().ok(),
+      hash: fnv1a(&bytes),
+      contents: String::from_utf8_lossy(&bytes).to_string(),
+    })
+  }
+
+  //the map as it is now if it changed since the fingerprint was taken
+  pub fn changed(&self) -> Result<Option<MapFingerprint>> {
+    let metadata = fs::metadata(&self.path).with_context(|| format!("[check] couldn't read {}", self.path))?;
+    if metadata.len() == self.len && metadata.modified().ok() == self.modified {
+      return Ok(None);
+    }
+
+    let current = MapFingerprint::read(&self.path)?;
+    if current.len == self.len && current.hash == self.hash {
+      return Ok(None);
+    }
+    Ok(Some(current))
+  }
+
+  //summary of the timing point, hit object and other changes from this fingerprint to a newer one
+  pub fn describe_changes(&self, newer: &MapFingerprint) -> String {
+    let (old_points, old_hits, old_other) = split_map(&self.contents);
+    let (new_points, new_hits, new_other) = split_map(&newer.contents);
+    let mut out_string = String::new();
+
+    let timing_diffs = diff::diff_timing_points(&old_points, &new_points);
+    if timing_diffs.len() > 0 {
+      out_string += &format!("{} timing point changes\n", timing_diffs.len());
+      out_string += &diff::format_table(&timing_diffs);
+    }
+
+    //hit objects are compared as lines, counting duplicates
+    let mut hit_counts: HashMap<&str, i32> = HashMap::new();
+    for hit in old_hits.iter() {
+      *hit_counts.entry(hit.data.as_str()).or_insert(0) -= 1;
+    }
+    for hit in new_hits.iter() {
+      *hit_counts.entry(hit.data.as_str()).or_insert(0) += 1;
+    }
+    let added: i32 = hit_counts.values().filter(|c| **c > 0).sum();
+    let removed: i32 = -hit_counts.values().filter(|c| **c < 0).sum::<i32>();
+    if added > 0 || removed > 0 {
+      out_string += &format!("{} hit objects added, {} removed\n", added, removed);
+    }
+
+    if old_other != new_other {
+      out_string += "other sections changed\n";
+    }
+
+    if out_string.len() == 0 {
+      out_string += "only formatting changed\n";
+    }
+    out_string
+  }
+}
+
+//timing points, hit objects and the remaining lines of a map
+fn split_map(contents: &str) -> (Vec<MapObject>, Vec<MapObject>, Vec<String>) {
+  let mut points = Vec::new();
+  let mut hits = Vec::new();
+  let mut other = Vec::new();
+
+  for line in OsuFile::parse(contents).lines.iter() {
+    match line.content() {
+      Some(s) if line.section == "TimingPoints" => match svt::create_map_object(s.to_string(), true) {
+        Ok(obj) => points.push(obj),
+        Err(_) => other.push(s.to_string()),
+      },
+      Some(s) if line.section == "HitObjects" => match svt::create_map_object(s.to_string(), false) {
+        Ok(obj) => hits.push(obj),
+        Err(_) => other.push(s.to_string()),
+      },
+      _ if !line.is_blank() => other.push(line.text.trim().to_string()),
+      _ => {},
+    }
+  }
+
+  points.sort_by_key(|k| (k.time, k.class));
+  (points, hits, other)
+}
+
+//64 bit fnv-1a, stable across runs unlike the std hasher
+fn fnv1a(bytes: &[u8]) -> u64 {
+  let mut hash: u64 = 0xcbf29ce484222325;
+  for byte in bytes.iter() {
+    hash ^= *byte as u64;
+    hash = hash.wrapping_mul(0x100000001b3);
+  }
+  hash
+}
@@ -0,0 +1,222 @@
+use anyhow::{anyhow, Result, Context};
+use serde::{Serialize, Deserialize};
+
+use std::cmp;
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::osufile::OsuFile;
+use crate::svt;
+
+//undo/redo history of a map, kept in .svt/<map filename>/ beside the map so it survives restarts
+//every state the map has been in is a numbered snapshot, and the map is at the current one unless it was edited outside svt since
+//applying after undoing drops the states that could have been redone
+
+const HISTORY_FOLDER: &str = ".svt";
+const HISTORY_FILE: &str = "history.json";
+//an unreadable history file is moved here when a new history is started
+const UNREADABLE_HISTORY_FILE: &str = "history.unreadable.json";
+//labels of states that weren't made by svt
+const BEFORE_SVT_LABEL: &str = "before svt";
+const EXTERNAL_EDIT_LABEL: &str = "edited outside svt";
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct HistoryState {
+  pub label: String,
+  pub snapshot: String,
+  //seconds since the unix epoch
+  pub time: u64,
+}
+
+#[derive(Serialize, Deserialize, Default, Debug)]
+#[serde(default)]
+pub struct History {
+  pub states: Vec<HistoryState>,
+  pub current: usize,
+  next_id: u64,
+  #[serde(skip)]
+  map: PathBuf,
+  #[serde(skip)]
+  folder: PathBuf,
+  //set when the history file couldn't be read and this history was started over
+  #[serde(skip)]
+  pub was_reset: bool,
+}
+
+impl History {
+  //load the history of a map, or start an empty one
+  pub fn load(map: &str) -> Result<History> {
+    let (map, folder) = history_location(map)?;
+
+    let mut history = match fs::read_to_string(folder.join(HISTORY_FILE)) {
+      Ok(history_string) => serde_json::from_str(&history_string).context("[history] history file is corrupted")?,
+      Err(_) => History::default(),
+    };
+    history.map = map;
+    history.folder = folder;
+
+    Ok(history)
+  }
+
+  //load the history of a map to change it, starting a new one if the history file can't be read so the change isn't blocked
+  //the unreadable file is kept beside the new one, and its snapshots stay where they are
+  pub fn load_or_reset(map: &str) -> Result<History> {
+    let err = match History::load(map) {
+      Ok(history) => return Ok(history),
+      Err(err) => err,
+    };
+    eprintln!("{:#}, starting a new history", err);
+
+    let (map, folder) = history_location(map)?;
+    fs::rename(folder.join(HISTORY_FILE), folder.join(UNREADABLE_HISTORY_FILE)).context("[history] couldn't move the unreadable history aside")?;
+
+    //new snapshots are numbered after the ones already in the folder
+    let next_id = fs::read_dir(&folder).map(|entries| {
+      entries.filter_map(|entry| entry.ok())
+        .filter_map(|entry| entry.path().file_stem().and_then(|stem| stem.to_str()).and_then(|stem| stem.parse::<u64>().ok()))
+        .max().map_or(0, |id| id + 1)
+    }).unwrap_or(0);
+
+    Ok(History{next_id: next_id, map: map, folder: folder, was_reset: true, ..Default::default()})
+  }
+
+  pub fn can_undo(&self) -> bool {
+    self.current > 0
+  }
+
+  pub fn can_redo(&self) -> bool {
+    self.current + 1 < self.states.len()
+  }
+
+  //snapshot the map before changing it, unless it is still at the current state
+  pub fn checkpoint(&mut self) -> Result<()> {
+    //nothing to go back to for a map that doesn't exist yet
+    if !self.map.exists() {
+      return Ok(());
+    }
+
+    if self.states.len() > 0 && self.at_current()? {
+      return Ok(());
+    }
+
+    let label = if self.states.len() == 0 { BEFORE_SVT_LABEL } else { EXTERNAL_EDIT_LABEL };
+    self.push_state(label)
+  }
+
+  //snapshot the map after a change
+  pub fn record(&mut self, label: &str) -> Result<()> {
+    self.push_state(label)
+  }
+
+  //restore the previous state, returning the label of the change that was undone
+  pub fn undo(&mut self) -> Result<String> {
+    self.checkpoint()?;
+    if !self.can_undo() {
+      return Err(anyhow!("[undo] nothing to undo"));
+    }
+
+    let label = self.states[self.current].label.clone();
+    self.current -= 1;
+    self.restore_current()?;
+
+    Ok(label)
+  }
+
+  //restore the next state, returning the label of the change that was redone
+  pub fn redo(&mut self) -> Result<String> {
+    //redoing over changes made outside svt would lose them
+    if self.states.len() > 0 && !self.at_current()? {
+      return Err(anyhow!("[redo] map was edited outside svt since the last undo"));
+    }
+    if !self.can_redo() {
+      return Err(anyhow!("[redo] nothing to redo"));
+    }
+
+    self.current += 1;
+    self.restore_current()?;
+
+    Ok(self.states[self.current].label.clone())
+  }
+
+  //timing point lines added by changes svt made up to the current state, as opposed to lines from the map before svt or other edits
+  pub fn tool_lines(&self) -> Result<HashSet<String>> {
+    let mut lines = HashSet::new();
+    for i in 1..cmp::min(self.current + 1, self.states.len()) {
+      let label = self.states[i].label.as_str();
+      if label == BEFORE_SVT_LABEL || label == EXTERNAL_EDIT_LABEL {
+        continue;
+      }
+
+      let before = self.snapshot_timing_lines(i - 1)?;
+      let after = self.snapshot_timing_lines(i)?;
+      lines.extend(after.difference(&before).cloned());
+    }
+
+    Ok(lines)
+  }
+
+  fn snapshot_timing_lines(&self, i: usize) -> Result<HashSet<String>> {
+    let snapshot = fs::read_to_string(self.folder.join(&self.states[i].snapshot)).context("[history] couldn't read snapshot")?;
+    let lines = OsuFile::parse(&snapshot).lines.iter()
+      .filter(|line| line.section == "TimingPoints")
+      .filter_map(|line| line.content().map(|s| s.to_string()))
+      .collect();
+    Ok(lines)
+  }
+
+  //whether the map is unchanged since the current state
+  fn at_current(&self) -> Result<bool> {
+    let map_bytes = fs::read(&self.map).context("[history] couldn't read map")?;
+    let state_bytes = fs::read(self.folder.join(&self.states[self.current].snapshot)).context("[history] couldn't read snapshot")?;
+    Ok(map_bytes == state_bytes)
+  }
+
+  //snapshots are written back like any other change, so an interrupted undo can't leave a truncated map
+  fn restore_current(&self) -> Result<()> {
+    let snapshot = fs::read_to_string(self.folder.join(&self.states[self.current].snapshot)).context("[history] couldn't read snapshot")?;
+    let map = self.map.to_str().ok_or(anyhow!("[history] invalid map path"))?;
+    svt::write_map(map, &snapshot).context("[history] couldn't restore snapshot")?;
+    self.save()
+  }
+
+  //add the map as it is now after the current state, dropping any states that could have been redone
+  fn push_state(&mut self, label: &str) -> Result<()> {
+    fs::create_dir_all(&self.folder).context("[history] couldn't create history folder")?;
+
+    if self.states.len() > 0 {
+      for state in self.states.drain(self.current + 1..) {
+        let _ = fs::remove_file(self.folder.join(&state.snapshot));
+      }
+    }
+
+    let snapshot = format!("{:04}.osu", self.next_id);
+    fs::copy(&self.map, self.folder.join(&snapshot)).context("[history] couldn't snapshot map")?;
+    self.next_id += 1;
+
+    let time = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    self.states.push(HistoryState{label: label.to_string(), snapshot: snapshot, time: time});
+    self.current = self.states.len() - 1;
+
+    self.save()
+  }
+
+  //written to a temp file first so the history file is always either the old or the new one
+  fn save(&self) -> Result<()> {
+    let history_string = serde_json::to_string_pretty(self).context("[history] couldn't serialize history")?;
+    let history_file = self.folder.join(HISTORY_FILE);
+    let tmp_file = self.folder.join(format!("{}.svt-tmp", HISTORY_FILE));
+    let tmp_filename = tmp_file.to_str().ok_or(anyhow!("[history] invalid history path"))?;
+    svt::write_synced(tmp_filename, &history_string).context("[history] couldn't save history")?;
+    fs::rename(&tmp_file, &history_file).context("[history] couldn't save history")
+  }
+}
+
+//map path and the folder its history is kept in
+fn history_location(map: &str) -> Result<(PathBuf, PathBuf)> {
+  let map = PathBuf::from(map);
+  let map_name = map.file_name().ok_or(anyhow!("[history] invalid map path"))?;
+  let folder = map.parent().unwrap_or(Path::new("")).join(HISTORY_FOLDER).join(map_name);
+  Ok((map, folder))
+}
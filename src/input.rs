@@ -0,0 +1,477 @@
+use anyhow::{anyhow, Result, Context};
+
+use std::collections::HashMap;
+
+use crate::svt::{self, MapObject, SVT};
+
+//input grammar for start/end points, resolved against the currently loaded map
+//each non-empty line is either one or more raw timing point lines (111376,-76.92308,4,1,0,100,0,1)
+//or a single point written as <time> [<sv>x] [<vol>%], for example:
+//  01:51:376 1.25x 60%
+//  u2+8b 0.8x
+//  bm3-1/2b 40%
+//<time> is an editor timestamp (mm:ss:ms), a time in ms, a red line (u<N>) or a bookmark (bm<N>),
+//optionally followed by offsets in beats (+4b, -1/2b) or milliseconds (+20ms)
+//sv and volume default to whatever is active in the map at that time
+//consecutive points are paired up into start/end ranges
+//
+//a selection copied from the editor is a complete range on its own, with optional start/end sv and volume:
+//  00:12:345 (12345|2,12400|0) - 1.0x 1.5x
+//  00:12:345 (1,2,3,4) - 0.8x 60% 80%
+//the range spans the selected objects and only those objects are affected
+//
+//ranges can also be written directly as <time>..<time>, with the same optional start/end sv and volume:
+//  bm3..bm4 1.0x 1.5x
+//  bm* 0.8x 1.2x (every consecutive pair of bookmarks)
+//and any time can be given a name for later lines to use as an anchor:
+//  chorus = bm3
+//  chorus..chorus+16b 1.0x 2.0x
+//
+//a select line restricts all following ranges to the objects matching a query (see query.rs),
+//until the next select line or "select all":
+//  select hits where finish and kiai
+//
+//a snap line changes the snapping divisors used by all following ranges, until the next snap line or "snap default":
+//  snap 1/3 1/6
+//
+//a clear line removes the inherited lines svt placed in a range (or all of them with "all"), from the start up to but not including the end
+//"reset" puts a line with the sv, volume and sampleset from before the range at its start, and the state at the end is always kept:
+//  clear bm3..bm4
+//  clear all 01:00:000..01:10:000 reset
+
+//finest snapping divisor accepted, finer than anything the editor offers
+const MAX_DIVISOR_DENOM: u32 = 64;
+
+//start/end pair to apply a function between, optionally restricted to an explicit set of objects
+#[derive(Clone, Debug)]
+pub struct InputRange {
+  pub start: MapObject,
+  pub end: MapObject,
+  pub selection: Option<Vec<MapObject>>,
+  pub snappings: Option<Vec<MapObject>>,
+  pub clear: Option<Clear>,
+}
+
+//which lines a clear range removes, and whether to reset to the state before it
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Clear {
+  pub all: bool,
+  pub reset: bool,
+}
+
+impl SVT {
+  //parse the full input text into start/end ranges
+  pub fn parse_input(&self, text: &str) -> Result<Vec<InputRange>> {
+    let mut ranges = Vec::new();
+    let mut points = Vec::new();
+    let mut markers = HashMap::new();
+    let mut query_selection: Option<Vec<MapObject>> = None;
+    let mut snappings: Option<Vec<MapObject>> = None;
+
+    for line in text.lines() {
+      let tokens: Vec<&str> = line.split_whitespace().collect();
+      if tokens.len() == 0 {
+        continue;
+      }
+
+      //query selection for the following ranges
+      if tokens[0] == "select" {
+        let query = tokens[1..].join(" ");
+        query_selection = if query == "all" {
+          None
+        } else {
+          Some(self.run_query(&query).with_context(|| format!("[input] invalid query: {}", query))?)
+        };
+        continue;
+      }
+
+      //snapping divisors for the following ranges, replacing the ones loaded with the map
+      if tokens[0] == "snap" {
+        let divisors = tokens[1..].join(" ");
+        snappings = if divisors == "default" {
+          None
+        } else {
+          Some(self.generate_snappings(&parse_divisors(&divisors).with_context(|| format!("[input] invalid snapping: {}", divisors))?))
+        };
+        continue;
+      }
+
+      //lines to remove instead of points to apply
+      if tokens[0] == "clear" {
+        if points.len() % 2 == 1 {
+          return Err(anyhow!("[input] range found between start/end points: {}", line.trim()));
+        }
+        ranges.extend(self.parse_clear(&tokens, &markers).with_context(|| format!("[input] invalid clear: {}", line.trim()))?);
+        continue;
+      }
+
+      //named marker definition
+      if tokens.len() == 3 && tokens[1] == "=" {
+        let name = tokens[0];
+        if !name.starts_with(|c: char| c.is_ascii_alphabetic()) || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+          return Err(anyhow!("[input] invalid marker name {}", name));
+        }
+        let time = self.parse_time(tokens[2], &markers).with_context(|| format!("[input] invalid marker: {}", line.trim()))?;
+        markers.insert(name.to_string(), time);
+        continue;
+      }
+
+      //editor selections and explicit ranges can't be paired with a dangling start point
+      let is_selection = line.contains("(") && line.contains(")");
+      let is_range = tokens[0].contains("..") || tokens[0] == "bm*";
+      if (is_selection || is_range) && points.len() % 2 == 1 {
+        return Err(anyhow!("[input] range found between start/end points: {}", line.trim()));
+      }
+
+      if is_selection {
+        ranges.push(self.parse_selection(line, &markers).with_context(|| format!("[input] invalid selection: {}", line.trim()))?);
+        continue;
+      } else if is_range {
+        let mut new_ranges = self.parse_range(&tokens, &markers).with_context(|| format!("[input] invalid range: {}", line.trim()))?;
+        for range in new_ranges.iter_mut() {
+          range.selection = query_selection.clone();
+          range.snappings = snappings.clone();
+        }
+        ranges.extend(new_ranges);
+        continue;
+      }
+
+      if tokens.iter().all(|t| t.contains(",")) {
+        //raw timing point lines, possibly several on one line
+        for token in tokens {
+          points.push(svt::create_map_object(token.to_string(), true).context("[input] timing point format error")?);
+        }
+      } else {
+        points.push(self.parse_point(&tokens, &markers).with_context(|| format!("[input] invalid point: {}", line.trim()))?);
+      }
+
+      //points pair up in order, a line can hold several pairs and leave a start point for the next line
+      while points.len() >= 2 {
+        let start = points.remove(0);
+        let end = points.remove(0);
+        ranges.push(InputRange{start: start, end: end, selection: query_selection.clone(), snappings: snappings.clone(), clear: None});
+      }
+    }
+
+    if points.len() > 0 {
+      return Err(anyhow!("[input] start point without an end point: {}", points[0].data));
+    }
+
+    Ok(ranges)
+  }
+
+  //parse an editor selection like 00:12:345 (1,2,3) - [<sv>x] [<sv>x] [<vol>%] [<vol>%]
+  fn parse_selection(&self, line: &str, markers: &HashMap<String, i32>) -> Result<InputRange> {
+    let open_idx = line.find("(").unwrap();
+    let close_idx = line.rfind(")").unwrap();
+    if close_idx < open_idx {
+      return Err(anyhow!("[input] mismatched parentheses"));
+    }
+
+    let timestamp = self.parse_anchor(line[..open_idx].trim(), markers)?.round() as i32;
+    let objs: Vec<&str> = line[open_idx + 1..close_idx].split(",").map(|o| o.trim()).collect();
+
+    let mut times = if objs.iter().all(|o| o.contains("|")) {
+      //mania selections reference objects by time|column
+      let mut times = Vec::new();
+      for obj in objs.iter() {
+        let time = obj.split("|").nth(0).unwrap_or("");
+        times.push(time.parse::<i32>().with_context(|| format!("[input] invalid object time {}", obj))?);
+      }
+      times
+    } else {
+      //other modes reference objects by combo number, starting from the timestamp
+      let mut combos = Vec::new();
+      for obj in objs.iter() {
+        combos.push(obj.parse::<i32>().with_context(|| format!("[input] invalid combo number {}", obj))?);
+      }
+      self.resolve_combo_numbers(timestamp, &combos)?
+    };
+    times.sort();
+    times.dedup();
+
+    let selection: Vec<MapObject> = self.all_objs.iter().filter(|obj| obj.class == 3 && times.binary_search(&obj.time).is_ok()).cloned().collect();
+    if selection.len() == 0 {
+      return Err(anyhow!("[input] no hit objects found for selection"));
+    }
+
+    //everything after the closing parenthesis is sv/volume for the start and end of the selection
+    let tokens: Vec<&str> = line[close_idx + 1..].split_whitespace().collect();
+    let (svs, volumes) = parse_sv_volume(&tokens, 2)?;
+
+    let start_time = selection[0].time;
+    let end_time = selection[selection.len() - 1].time;
+    let mut range = self.make_range(start_time, end_time, &svs, &volumes, line.trim())?;
+    range.selection = Some(selection);
+
+    Ok(range)
+  }
+
+  //parse <time>..<time> or bm* followed by optional start/end sv and volume
+  fn parse_range(&self, tokens: &[&str], markers: &HashMap<String, i32>) -> Result<Vec<InputRange>> {
+    let (svs, volumes) = parse_sv_volume(&tokens[1..], 2)?;
+    let data = tokens.join(" ");
+
+    if tokens[0] == "bm*" {
+      if self.bookmarks.len() < 2 {
+        return Err(anyhow!("[input] need at least 2 bookmarks, found {}", self.bookmarks.len()));
+      }
+
+      let mut ranges = Vec::new();
+      for pair in self.bookmarks.windows(2) {
+        ranges.push(self.make_range(pair[0], pair[1], &svs, &volumes, &data)?);
+      }
+      return Ok(ranges);
+    }
+
+    let (start_str, end_str) = tokens[0].split_once("..").unwrap();
+    let start_time = self.parse_time(start_str, markers)?;
+    let end_time = self.parse_time(end_str, markers)?;
+
+    Ok(vec![self.make_range(start_time, end_time, &svs, &volumes, &data)?])
+  }
+
+  //parse clear [all] <time>..<time> [reset] or clear [all] bm* [reset]
+  fn parse_clear(&self, tokens: &[&str], markers: &HashMap<String, i32>) -> Result<Vec<InputRange>> {
+    let mut tokens = &tokens[1..];
+    let all = tokens.first() == Some(&"all");
+    if all {
+      tokens = &tokens[1..];
+    }
+    let reset = tokens.last() == Some(&"reset");
+    if reset {
+      tokens = &tokens[..tokens.len() - 1];
+    }
+
+    if tokens.len() != 1 || !(tokens[0].contains("..") || tokens[0] == "bm*") {
+      return Err(anyhow!("[input] expected a single range"));
+    }
+
+    let mut ranges = self.parse_range(tokens, markers)?;
+    for range in ranges.iter_mut() {
+      if range.start.time >= range.end.time {
+        return Err(anyhow!("[input] clear range must end after it starts"));
+      }
+      range.clear = Some(Clear{all: all, reset: reset});
+    }
+    Ok(ranges)
+  }
+
+  //build a range between two times, using the first/last sv and volume for the start/end points
+  fn make_range(&self, start_time: i32, end_time: i32, svs: &[f64], volumes: &[i32], data: &str) -> Result<InputRange> {
+    let start = self.make_point(start_time, svs.first().copied(), volumes.first().copied(), data)?;
+    let end = self.make_point(end_time, svs.last().copied(), volumes.last().copied(), data)?;
+
+    Ok(InputRange{start: start, end: end, selection: None, snappings: None, clear: None})
+  }
+
+  //find the times of the hit objects with the given combo numbers, walking forward from the object at time t
+  fn resolve_combo_numbers(&self, t: i32, combos: &[i32]) -> Result<Vec<i32>> {
+    //combo numbers restart on new combos, spinners and the object after a spinner
+    let mut hits = Vec::new();
+    let mut combo = 0;
+    let mut after_spinner = false;
+    for obj in self.all_objs.iter().filter(|obj| obj.class == 3) {
+      let spinner = obj.hit_type & 8 != 0;
+      if combo == 0 || obj.hit_type & 4 != 0 || spinner || after_spinner {
+        combo = 1;
+      } else {
+        combo += 1;
+      }
+      after_spinner = spinner;
+      hits.push((obj.time, combo));
+    }
+
+    let mut times = Vec::new();
+    let mut hits_iter = hits.iter().skip_while(|(time, _)| *time < t);
+    for target in combos.iter() {
+      match hits_iter.find(|(_, combo)| combo == target) {
+        Some((time, _)) => times.push(*time),
+        None => return Err(anyhow!("[input] no object with combo number {} after {}", target, t)),
+      }
+    }
+
+    if times.len() == 0 {
+      return Err(anyhow!("[input] empty selection"));
+    }
+
+    Ok(times)
+  }
+
+  //parse a single <time> [<sv>x] [<vol>%] point into an inherited timing point
+  fn parse_point(&self, tokens: &[&str], markers: &HashMap<String, i32>) -> Result<MapObject> {
+    let time = self.parse_time(tokens[0], markers)?;
+    let (svs, volumes) = parse_sv_volume(&tokens[1..], 1)?;
+
+    self.make_point(time, svs.first().copied(), volumes.first().copied(), &tokens.join(" "))
+  }
+
+  //build an inherited timing point at time t, falling back on the sv/volume active in the map at that time
+  fn make_point(&self, t: i32, sv: Option<f64>, volume: Option<i32>, data: &str) -> Result<MapObject> {
+    let (_, active_sv, active_volume) = self.timing_state_at(t);
+    let sv = sv.unwrap_or(active_sv);
+    let volume = volume.unwrap_or(active_volume);
+
+    if sv <= 0.0 {
+      return Err(anyhow!("[input] sv should be > 0"));
+    }
+
+    Ok(MapObject{
+      class: 1,
+      time: t,
+      beatlength: -100.0 / sv,
+      meter: 4,
+      volume: volume,
+      data: String::from(data),
+      ..Default::default()
+    })
+  }
+
+  //parse <anchor>[(+|-)<offset>]... into a time in ms
+  pub fn parse_time(&self, token: &str, markers: &HashMap<String, i32>) -> Result<i32> {
+    //split anchor from offsets at every sign that isn't the leading one
+    let split_idx = token.char_indices().skip(1).find(|(_, c)| *c == '+' || *c == '-').map(|(i, _)| i).unwrap_or(token.len());
+    let (anchor, offsets) = token.split_at(split_idx);
+
+    let anchor_time = self.parse_anchor(anchor, markers)?;
+    let (beatlength, _, _) = self.timing_state_at(anchor_time.round() as i32);
+
+    let mut time = anchor_time;
+    let mut rest = offsets;
+    while rest.len() > 0 {
+      let next_idx = rest.char_indices().skip(1).find(|(_, c)| *c == '+' || *c == '-').map(|(i, _)| i).unwrap_or(rest.len());
+      let (offset, remaining) = rest.split_at(next_idx);
+      rest = remaining;
+
+      if let Some(ms) = offset.strip_suffix("ms") {
+        time += ms.parse::<f64>().with_context(|| format!("[input] invalid ms offset {}", offset))?;
+      } else if let Some(beats) = offset.strip_suffix("b") {
+        if beatlength <= 0.0 {
+          return Err(anyhow!("[input] no uninherited line to count beats from"));
+        }
+        time += parse_fraction(beats).with_context(|| format!("[input] invalid beat offset {}", offset))? * beatlength;
+      } else {
+        return Err(anyhow!("[input] offset {} should end in b or ms", offset));
+      }
+    }
+
+    Ok(time.round() as i32)
+  }
+
+  //resolve a single anchor (marker, timestamp, ms, red line or bookmark) to a time in ms
+  fn parse_anchor(&self, anchor: &str, markers: &HashMap<String, i32>) -> Result<f64> {
+    if let Some(t) = markers.get(anchor) {
+      Ok(*t as f64)
+    } else if anchor.contains(":") {
+      //editor timestamp, mm:ss:ms
+      let parts: Vec<&str> = anchor.split(":").collect();
+      if parts.len() != 3 {
+        return Err(anyhow!("[input] timestamp {} should be mm:ss:ms", anchor));
+      }
+      let min = parts[0].parse::<i32>().context("[input] invalid timestamp minutes")?;
+      let sec = parts[1].parse::<i32>().context("[input] invalid timestamp seconds")?;
+      let ms = parts[2].parse::<i32>().context("[input] invalid timestamp ms")?;
+      Ok((min * 60000 + sec * 1000 + ms) as f64)
+    } else if let Some(idx) = anchor.strip_prefix("bm") {
+      let idx = idx.parse::<usize>().context("[input] invalid bookmark index")?;
+      match self.bookmarks.get(idx.wrapping_sub(1)) {
+        Some(t) => Ok(*t as f64),
+        None => Err(anyhow!("[input] bookmark {} not found ({} in map)", idx, self.bookmarks.len())),
+      }
+    } else if let Some(idx) = anchor.strip_prefix("u") {
+      let idx = idx.parse::<usize>().context("[input] invalid red line index")?;
+      match self.all_objs.iter().filter(|obj| obj.class == 0).nth(idx.wrapping_sub(1)) {
+        Some(obj) => Ok(obj.exact_time()),
+        None => Err(anyhow!("[input] red line {} not found", idx)),
+      }
+    } else {
+      anchor.parse::<f64>().with_context(|| format!("[input] invalid time {}", anchor))
+    }
+  }
+
+  //returns (red line beatlength, sv, volume) active at time t, or (0.0, 1.0, 100) before the first red line
+  pub fn timing_state_at(&self, t: i32) -> (f64, f64, i32) {
+    let mut beatlength = 0.0;
+    let mut sv = 1.0;
+    let mut volume = 100;
+
+    for obj in self.all_objs.iter() {
+      if obj.time > t {
+        break;
+      }
+      if obj.class == 0 {
+        beatlength = obj.beatlength;
+        sv = 1.0;
+        volume = obj.volume;
+      } else if obj.class == 1 {
+        sv = -100.0 / obj.beatlength;
+        volume = obj.volume;
+      }
+    }
+
+    (beatlength, sv, volume)
+  }
+}
+
+//parse trailing <sv>x and <vol>% tokens, allowing up to max_count of each
+fn parse_sv_volume(tokens: &[&str], max_count: usize) -> Result<(Vec<f64>, Vec<i32>)> {
+  let mut svs = Vec::new();
+  let mut volumes = Vec::new();
+
+  for token in tokens.iter() {
+    //editor timestamps are copied with a trailing dash
+    if *token == "-" {
+      continue;
+    } else if let Some(sv_str) = token.strip_suffix("x") {
+      svs.push(sv_str.parse::<f64>().context("[input] invalid sv")?);
+    } else if let Some(vol_str) = token.strip_suffix("%") {
+      volumes.push(vol_str.parse::<i32>().context("[input] invalid volume")?);
+    } else {
+      return Err(anyhow!("[input] unknown token {}", token));
+    }
+  }
+
+  if svs.len() > max_count || volumes.len() > max_count {
+    return Err(anyhow!("[input] too many sv/volume values, expected at most {} of each", max_count));
+  }
+
+  Ok((svs, volumes))
+}
+
+//parse a decimal (1.5) or fraction (3/4), keeping the sign
+pub fn parse_fraction(s: &str) -> Result<f64> {
+  if let Some((numer, denom)) = s.split_once("/") {
+    let numer = numer.parse::<f64>()?;
+    let denom = denom.parse::<f64>()?;
+    if denom == 0.0 {
+      return Err(anyhow!("[input] fraction denominator is 0"));
+    }
+    Ok(numer / denom)
+  } else {
+    Ok(s.parse::<f64>()?)
+  }
+}
+
+//parse a list of beat divisors like "1/4 1/3" into (numer, denom) pairs, a whole number n is read as n/1
+//both parts are whole numbers and the denominator is at most MAX_DIVISOR_DENOM, so every snapping step is at least 1/64 of a beat
+pub fn parse_divisors(text: &str) -> Result<Vec<(f64, f64)>> {
+  let mut divisors = Vec::new();
+  for token in text.split_whitespace() {
+    let (numer_str, denom_str) = token.split_once("/").unwrap_or((token, "1"));
+    let numer = numer_str.trim().parse::<u32>().with_context(|| format!("[input] invalid divisor {}, expected whole numbers like 1/4", token))?;
+    let denom = denom_str.trim().parse::<u32>().with_context(|| format!("[input] invalid divisor {}, expected whole numbers like 1/4", token))?;
+    if numer == 0 || denom == 0 {
+      return Err(anyhow!("[input] divisor {} should be > 0", token));
+    }
+    if denom > MAX_DIVISOR_DENOM {
+      return Err(anyhow!("[input] divisor {} is finer than 1/{}", token, MAX_DIVISOR_DENOM));
+    }
+    divisors.push((numer as f64, denom as f64));
+  }
+
+  if divisors.len() == 0 {
+    return Err(anyhow!("[input] no snapping divisors given"));
+  }
+
+  Ok(divisors)
+}
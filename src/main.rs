@@ -12,6 +12,17 @@ use nwg::NativeUi;
 
 mod ui;
 mod svt;
+mod input;
+mod filter;
+mod query;
+mod diff;
+mod cli;
+mod history;
+mod backup;
+mod fingerprint;
+mod preview;
+mod optimize;
+mod osufile;
 
 fn main() {
   /*
@@ -39,6 +50,17 @@ fn main() {
   panic!("hi");
   */
 
+  //command line tools print their output instead of opening the ui
+  let args: Vec<String> = std::env::args().skip(1).collect();
+  if args.first().map_or(false, |arg| ["--dry-run", "--backups", "--restore", "--clean-previews"].contains(&arg.as_str())) {
+    attach_parent_console();
+    if let Err(err) = cli::run(&args) {
+      eprintln!("{:#}", err);
+      std::process::exit(1);
+    }
+    return;
+  }
+
   nwg::init().expect("[main] failed to init nwg");
 
   //use Segoe UI with 16 size as default font
@@ -63,7 +85,7 @@ fn main() {
 
   if svt_ui.options.borrow().tooltips {
     let _res_ = nwg::Tooltip::builder()
-      .register(&svt_ui.inherited_text, "Paste timing point start/end pair(s) here. Copy/paste from timing panel. These timing points contain the start/end times, SVs, and volumes which are interpolated for the selected objects. (Example format: 111376,-76.92308,4,1,0,100,0,1)")
+      .register(&svt_ui.inherited_text, "Paste timing point start/end pair(s) here. Copy/paste from timing panel. These timing points contain the start/end times, SVs, and volumes which are interpolated for the selected objects. (Example format: 111376,-76.92308,4,1,0,100,0,1). Points can also be written one per line as <time> [sv] [vol], e.g. 01:51:376 1.25x 60% or u2+8b 0.8x (time as mm:ss:ms, ms, red line u<N> or bookmark bm<N>, with optional +/- offsets in beats (b) or ms). Selections copied from the editor (00:12:345 (1,2,3) - 1.0x 1.5x) only affect the selected objects. Ranges can be written as bm3..bm4 1.0x 1.5x, bm* applies to every pair of consecutive bookmarks, and name = <time> defines a named marker. A line like select hits where finish and kiai and beat % 1 == 0 (or select snaps 1/4 not near hits 20ms, or select barlines where kiai) restricts the following ranges to matching objects, until select all. Selected objects are changed whatever the target checkboxes and hit filter are set to. A line like snap 1/3 1/6 changes the snapping divisors for the following ranges, until snap default. clear bm3..bm4 removes the lines svt placed in a range (clear all for every inh. line), and reset adds a line with the SV/volume from before the range")
       .register(&svt_ui.lin_sv_check, "Change slider velocity linearly for selected objects (hits/snappings/inh. lines)")
       .register(&svt_ui.exp_sv_check, "Change slider velocity exponentially for selected objects (hits/snappings/inh. lines)")
       .register(&svt_ui.pol_sv_check, "Change slider velocity polynomially using exp. factor for selected objects (hits/snappings/inh. lines)")
@@ -72,25 +94,54 @@ fn main() {
       .register(&svt_ui.flat_sv_scale_check, "Flat multiplicative SV change for selected inh. lines")
       .register(&svt_ui.vol_check, "Change volume smoothly for selected objects (hits/snappings/inh. lines)")
       .register(&svt_ui.hit_check, "Change hitobjects (notes, spinners, sliders) between start/end points")
+      .register(&svt_ui.hit_filter_text, "Only change hitobjects matching all of these terms (leave empty for all). Terms: circle slider spinner hold, whistle finish clap plain, don kat big, nc (new combo), col<N> (mania column), normal soft drum, custom/custom<N>. Combine alternatives with | and negate with !, e.g. finish !big or col1|col4")
       .register(&svt_ui.snapping_check, "Change all snappings between start/end points")
       .register(&svt_ui.inh_check, "Change current inherited lines between start/end points")
+      .register(&svt_ui.repeat_check, "Change slider repeats between start/end points")
+      .register(&svt_ui.tail_check, "Change slider ends between start/end points (end times computed from slider length, SliderMultiplier and SV)")
+      .register(&svt_ui.tick_check, "Change slider ticks between start/end points")
+      .register(&svt_ui.spinner_end_check, "Change spinner and mania hold note ends between start/end points")
+      .register(&svt_ui.barline_check, "Change barlines (measure starts) between start/end points, following each red line's meter and omit first barline setting")
       .register(&svt_ui.offset_label, "(integer) Place new timing points at offset (in ms) from map object (negative offset for before, positive for after)")
       .register(&svt_ui.buffer_label, "(integer) Include map objects (in ms) before and after the start/end timing points, useful if objects are not perfectly snapped")
       .register(&svt_ui.min_spacing_label, "(integer) Minimum spacing around tool-placed points (in ms) where other points must either follow social distancing or be removed")
       .register(&svt_ui.pol_exp_label, "(decimal) Exponent for polynomial SV. Recommended values are [0.5, 1) for slowdowns and (1.0, 2.0] for speedups. Applied following a (sv_diff) * (t / t_diff)^exp curve")
       .register(&svt_ui.flat_sv_label, "(decimal) Amount of SV change to apply to each inherited line")
+      .register(&svt_ui.snapping_label, "One or more beat divisors to generate snappings from, separated by spaces (e.g. 1/4 1/3, whole numbers down to 1/64). Snappings shared between divisors are only changed once")
+      .register(&svt_ui.snapping_end_label, "Time to stop generating snappings and barlines at (mm:ss:ms, ms, u<N> or bm<N>). Leave empty to stop at the end of the last hit object")
       .register(&svt_ui.flat_sv_scale_label, "(decimal) Scaling factor to apply to each inherited line")
+      .register(&svt_ui.merge_policy_label, "What to do with existing inh. lines within min spacing of a new point: replace them, keep them and skip the new point, multiply the new SV by the existing SV, or take the new SV but keep the existing volume/sampleset or kiai/effects. Every conflict is listed in the apply report")
+      .register(&svt_ui.optimize_check, "Removes inherited lines that change nothing, such as repeats of the line before them or lines overridden within 1ms with no objects in between. Applying with no input only optimizes")
+      .register(&svt_ui.fix_sliders_check, "Changing SV under a slider changes its duration. If checked, slider lengths are rewritten so slider ends stay on their original snaps, otherwise affected sliders are only listed in the apply report")
       .register(&svt_ui.ign_bpm_check, "End timing point SV is normally relative to end timing point BPM, but if checked, can be made relative to start timing point BPM")
       .register(&svt_ui.open_button, "Select map to change")
       .register(&svt_ui.in_filename, "Map being edited")
       .register(&svt_ui.out_filename, "Output location")
       .register(&svt_ui.preview_check, "If enabled, creates a preview diff alongside your current diff which shows how the changes would potentially look without touching the original")
       .register(&svt_ui.apply_button, "Apply SV/vol changes")
-      .register(&svt_ui.undo_button, "Undo most recent change")
+      .register(&svt_ui.undo_button, "Undo the most recent change to this map. Every change is kept in a .svt folder beside the map, so undo works across restarts")
+      .register(&svt_ui.redo_button, "Redo the most recently undone change")
       .build(&mut tooltip);
     tooltip.set_delay_time(Some(50));
   }
 
   //start handling events
   nwg::dispatch_thread_events();
-}
\ No newline at end of file
+}
+
+//release builds use the windows subsystem and start without a console, so command line output goes to the console svt was run from
+//output redirected to a file or pipe already has somewhere to go and isn't affected
+#[cfg(windows)]
+fn attach_parent_console() {
+  const ATTACH_PARENT_PROCESS: u32 = 0xFFFFFFFF;
+  #[link(name = "kernel32")]
+  extern "system" {
+    fn AttachConsole(process_id: u32) -> i32;
+  }
+  unsafe {
+    AttachConsole(ATTACH_PARENT_PROCESS);
+  }
+}
+
+#[cfg(not(windows))]
+fn attach_parent_console() {}
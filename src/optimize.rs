@@ -0,0 +1,111 @@
+use crate::svt::{self, MapObject};
+
+//removes inherited lines that don't change anything in the map:
+//  lines with the same sv, sampleset, sample index, volume and effects as the line in effect before them
+//  lines overridden by the next line within 1ms, when no object falls in between and kiai isn't toggled
+//uninherited lines are always kept, they set bpm, meter and barlines
+
+//timing points should be sorted chronologically with red lines before green lines at the same time
+//object_times are the times of everything that uses a timing point (hits, slider repeats/tails/ticks, spinner/hold ends), sorted
+//returns the kept lines and the removed ones
+pub fn optimize_timing_points(points: Vec<MapObject>, object_times: &[i32]) -> (Vec<MapObject>, Vec<MapObject>) {
+  let mut kept: Vec<MapObject> = Vec::new();
+  let mut removed: Vec<MapObject> = Vec::new();
+
+  for (i, point) in points.iter().enumerate() {
+    //the line in effect before this one, nothing to compare green lines before the first red line with
+    let state = match kept.last() {
+      Some(state) if point.uninherited == 0 => state,
+      _ => {
+        kept.push(point.clone());
+        continue;
+      },
+    };
+
+    let unchanged = same_sv(state, point) && same_sounds(state, point) && state.effects == point.effects;
+
+    //lines can be at decimal times, so an object at t uses this line if it's at or after this line's exact time and before the next one's
+    let overridden = match points.get(i + 1) {
+      Some(next) if next.exact_time() - point.exact_time() <= 1.0 => {
+        let first_after = object_times.partition_point(|t| (*t as f64) < point.exact_time());
+        let object_between = object_times.get(first_after).map_or(false, |t| (*t as f64) < next.exact_time());
+        !object_between && state.effects & 1 == point.effects & 1
+      },
+      _ => false,
+    };
+
+    if unchanged || overridden {
+      eprintln!("[optimize] {} removed {}{}", svt::format_timestamp(point.time), point.data, if unchanged { "" } else { " (overridden)" });
+      removed.push(point.clone());
+    } else {
+      kept.push(point.clone());
+    }
+  }
+
+  (kept, removed)
+}
+
+//red lines reset sv to 1.0x
+fn same_sv(state: &MapObject, point: &MapObject) -> bool {
+  let state_beatlength = if state.uninherited == 1 { -100.0 } else { state.beatlength };
+  state_beatlength == point.beatlength
+}
+
+fn same_sounds(state: &MapObject, point: &MapObject) -> bool {
+  state.sampleset == point.sampleset && state.sampleindex == point.sampleindex && state.volume == point.volume
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn red(time: i32, volume: i32) -> MapObject {
+    MapObject{class: 0, time: time, beatlength: 500.0, meter: 4, sampleset: 2, volume: volume, uninherited: 1, data: format!("{},500,4,2,0,{},1,0", time, volume), ..Default::default()}
+  }
+
+  fn green(time: f64, beatlength: f64, volume: i32, effects: i32) -> MapObject {
+    MapObject{class: 1, time: time.floor() as i32, time_fraction: time - time.floor(), beatlength: beatlength, meter: 4, sampleset: 2, volume: volume, effects: effects,
+      data: format!("{},{},4,2,0,{},0,{}", time, beatlength, volume, effects), ..Default::default()}
+  }
+
+  fn removed_times(points: Vec<MapObject>, object_times: &[i32]) -> Vec<f64> {
+    optimize_timing_points(points, object_times).1.iter().map(|point| point.exact_time()).collect()
+  }
+
+  #[test]
+  fn removes_identical_lines() {
+    let points = vec![red(0, 60), green(1000.0, -50.0, 60, 0), green(2000.0, -50.0, 60, 0), green(3000.0, -50.0, 70, 0)];
+    assert_eq!(removed_times(points, &[1000, 2000, 3000]), vec![2000.0]);
+  }
+
+  #[test]
+  fn removes_overridden_lines_without_objects_between() {
+    let points = vec![red(0, 60), green(1000.0, -50.0, 60, 0), green(1001.0, -80.0, 60, 0)];
+    assert_eq!(removed_times(points.clone(), &[]), vec![1000.0]);
+    assert_eq!(removed_times(points, &[1000]), Vec::<f64>::new());
+
+    //an object in the same ms but before a decimal line still uses the line before it
+    let points = vec![red(0, 60), green(1000.0, -50.0, 60, 0), green(1000.5, -80.0, 60, 0)];
+    assert_eq!(removed_times(points, &[1000]), Vec::<f64>::new());
+  }
+
+  #[test]
+  fn keeps_lines_more_than_1ms_apart() {
+    //floored times would be 1ms apart
+    let points = vec![red(0, 60), green(100.0, -50.0, 60, 0), green(101.9, -80.0, 60, 0)];
+    assert_eq!(removed_times(points, &[]), Vec::<f64>::new());
+  }
+
+  #[test]
+  fn keeps_kiai_toggles() {
+    let points = vec![red(0, 60), green(1000.0, -100.0, 60, 1), green(1001.0, -80.0, 60, 0)];
+    assert_eq!(removed_times(points, &[]), Vec::<f64>::new());
+  }
+
+  #[test]
+  fn green_line_after_red_line() {
+    //a red line resets sv to 1.0x, so a 1.0x line on it changes nothing
+    let points = vec![red(1000, 60), green(1000.0, -100.0, 60, 0), red(2000, 60), green(2000.0, -50.0, 60, 0)];
+    assert_eq!(removed_times(points, &[1000, 2000]), vec![1000.0]);
+  }
+}
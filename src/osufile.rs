@@ -0,0 +1,155 @@
+use anyhow::{Result, Context};
+
+use std::fs;
+
+//a .osu file split into lines, each tagged with the section it's in
+//tolerant of what editors and other tools leave behind:
+//  a utf-8 byte order mark before the format version
+//  crlf or lf line endings, the file's own style is used when writing it back
+//  a missing line ending after the last line, also kept when writing it back
+//  whitespace around section headers, key: value pairs and object lines
+//  // comment lines, which are kept but never parsed
+//  any section name, lines in unknown sections are carried through untouched
+
+const BOM: &str = "\u{feff}";
+
+#[derive(Clone, Debug)]
+pub struct OsuFile {
+  pub bom: bool,
+  pub line_ending: &'static str,
+  pub final_newline: bool,
+  pub lines: Vec<OsuLine>,
+}
+
+#[derive(Clone, Debug)]
+pub struct OsuLine {
+  //section name without brackets, empty before the first header
+  pub section: String,
+  //the line as written, without its line ending
+  pub text: String,
+}
+
+impl OsuFile {
+  pub fn read(path: &str) -> Result<OsuFile> {
+    let contents = fs::read_to_string(path).with_context(|| format!("[read] couldn't read {}", path))?;
+    Ok(OsuFile::parse(&contents))
+  }
+
+  pub fn parse(contents: &str) -> OsuFile {
+    let bom = contents.starts_with(BOM);
+    let contents = contents.trim_start_matches(BOM);
+    let line_ending = if contents.contains("\r\n") { "\r\n" } else { "\n" };
+    let final_newline = contents.len() == 0 || contents.ends_with("\n");
+
+    let mut section = String::new();
+    let mut lines = Vec::new();
+    for text in contents.lines() {
+      if let Some(name) = section_name(text) {
+        section = name.to_string();
+      }
+      lines.push(OsuLine{section: section.clone(), text: text.to_string()});
+    }
+
+    OsuFile{bom: bom, line_ending: line_ending, final_newline: final_newline, lines: lines}
+  }
+
+  //join lines written from this file the way it was written, with its bom and line endings
+  pub fn join(&self, lines: &[String]) -> String {
+    let mut out_string = String::new();
+    if self.bom {
+      out_string += BOM;
+    }
+    for (i, line) in lines.iter().enumerate() {
+      out_string += line;
+      if self.final_newline || i + 1 < lines.len() {
+        out_string += self.line_ending;
+      }
+    }
+    out_string
+  }
+}
+
+impl OsuLine {
+  pub fn is_header(&self) -> bool {
+    section_name(&self.text).is_some()
+  }
+
+  pub fn is_comment(&self) -> bool {
+    self.text.trim_start().starts_with("//")
+  }
+
+  pub fn is_blank(&self) -> bool {
+    self.text.trim().len() == 0
+  }
+
+  //the trimmed line if it holds data for its section
+  pub fn content(&self) -> Option<&str> {
+    if self.is_header() || self.is_comment() || self.is_blank() {
+      None
+    } else {
+      Some(self.text.trim())
+    }
+  }
+
+  //key and value of a key: value line, both trimmed
+  pub fn key_value(&self) -> Option<(&str, &str)> {
+    let mut tokens = self.content()?.splitn(2, ":");
+    let key = tokens.next()?.trim();
+    let value = tokens.next()?.trim();
+    Some((key, value))
+  }
+}
+
+//name of the section a header line starts, [<name>]
+fn section_name(text: &str) -> Option<&str> {
+  let text = text.trim();
+  if text.len() >= 2 && text.starts_with("[") && text.ends_with("]") {
+    Some(text[1..text.len() - 1].trim())
+  } else {
+    None
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  //parsing then joining every line as read gives back the file byte for byte
+  fn round_trip(contents: &str) -> String {
+    let osu_file = OsuFile::parse(contents);
+    let lines: Vec<String> = osu_file.lines.iter().map(|line| line.text.clone()).collect();
+    osu_file.join(&lines)
+  }
+
+  #[test]
+  fn round_trips_bom_and_crlf() {
+    let contents = "\u{feff}osu file format v14\r\n\r\n[General]\r\nSampleSet: Soft\r\n\r\n[TimingPoints]\r\n0,500,4,2,0,50,1,0\r\n";
+    let osu_file = OsuFile::parse(contents);
+    assert!(osu_file.bom);
+    assert_eq!(osu_file.line_ending, "\r\n");
+    assert_eq!(round_trip(contents), contents);
+
+    //without a line ending after the last line
+    let contents = "\u{feff}osu file format v14\r\n\r\n[HitObjects]\r\n256,192,1000,1,0";
+    assert_eq!(round_trip(contents), contents);
+  }
+
+  #[test]
+  fn round_trips_comments() {
+    let contents = "osu file format v14\n\n// written by hand\n[TimingPoints]\n//kiai starts here\n0,500,4,2,0,50,1,1\n";
+    let osu_file = OsuFile::parse(contents);
+    let comments: Vec<&OsuLine> = osu_file.lines.iter().filter(|line| line.is_comment()).collect();
+    assert_eq!(comments.len(), 2);
+    assert!(comments.iter().all(|line| line.content().is_none()));
+    assert_eq!(round_trip(contents), contents);
+  }
+
+  #[test]
+  fn round_trips_unknown_sections() {
+    let contents = "osu file format v14\n\n[Storyboard]\nSprite,Background,Centre,\"bg.jpg\",320,240\n F,0,0,1000,0,1\n\n[TimingPoints]\n0,500,4,2,0,50,1,0\n";
+    let osu_file = OsuFile::parse(contents);
+    let storyboard: Vec<&str> = osu_file.lines.iter().filter(|line| line.section == "Storyboard").filter_map(|line| line.content()).collect();
+    assert_eq!(storyboard, vec!["Sprite,Background,Centre,\"bg.jpg\",320,240", "F,0,0,1000,0,1"]);
+    assert_eq!(round_trip(contents), contents);
+  }
+}
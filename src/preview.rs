@@ -0,0 +1,78 @@
+use anyhow::{Result, Context};
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::osufile::OsuFile;
+
+//preview difficulties are written beside the map as their own difficulty, <map> [<diff> (svt preview)].osu
+//they get their own version name and no beatmap id so osu! doesn't mistake them for the original
+//a preview is only ever recognised by both its file name and its version, so real difficulties are never cleaned up
+
+const PREVIEW_SUFFIX: &str = " (svt preview)";
+
+//version name of the preview of a difficulty
+pub fn preview_version(version: &str) -> String {
+  let version = version.trim();
+  if version.ends_with(PREVIEW_SUFFIX) {
+    version.to_string()
+  } else {
+    format!("{}{}", version, PREVIEW_SUFFIX)
+  }
+}
+
+//file the preview of a map is written to, unique per difficulty
+pub fn preview_filename(map: &str) -> Option<PathBuf> {
+  let path = Path::new(map);
+  let folder = path.parent()?;
+  let name = path.file_stem()?.to_str()?;
+
+  //maps are named <artist> - <title> (<mapper>) [<diff>].osu
+  let preview_name = match name.rfind("[") {
+    Some(i) if name.ends_with("]") => format!("{}[{}].osu", &name[..i], preview_version(&name[i + 1..name.len() - 1])),
+    _ => format!("{} [svt preview].osu", name),
+  };
+
+  Some(folder.join(preview_name))
+}
+
+//whether a file is a preview written by svt
+pub fn is_preview(path: &Path) -> bool {
+  let name = path.file_name().and_then(|n| n.to_str()).unwrap_or("");
+  if !name.ends_with(&format!("{}].osu", PREVIEW_SUFFIX)) && !name.ends_with("[svt preview].osu") {
+    return false;
+  }
+
+  let contents = fs::read_to_string(path).unwrap_or_default();
+  OsuFile::parse(&contents).lines.iter().any(|line| match line.key_value() {
+    Some(("Version", version)) => line.section == "Metadata" && version.ends_with(PREVIEW_SUFFIX),
+    _ => false,
+  })
+}
+
+//remove the preview of a map once its changes are applied to the map itself
+pub fn remove_preview(map: &str) -> Result<Option<PathBuf>> {
+  match preview_filename(map) {
+    Some(path) if path.exists() && is_preview(&path) => {
+      fs::remove_file(&path).with_context(|| format!("[preview] couldn't remove {}", path.display()))?;
+      Ok(Some(path))
+    },
+    _ => Ok(None),
+  }
+}
+
+//remove every preview in a map's set folder
+pub fn clean_previews(map: &str) -> Result<Vec<PathBuf>> {
+  let folder = Path::new(map).parent().unwrap_or(Path::new(""));
+  let mut removed = Vec::new();
+
+  for entry in fs::read_dir(folder).context("[preview] couldn't read map folder")? {
+    let path = entry.context("[preview] couldn't read map folder")?.path();
+    if is_preview(&path) {
+      fs::remove_file(&path).with_context(|| format!("[preview] couldn't remove {}", path.display()))?;
+      removed.push(path);
+    }
+  }
+
+  Ok(removed)
+}
@@ -0,0 +1,219 @@
+use anyhow::{anyhow, Result, Context};
+
+use crate::filter::{self, HitTerm};
+use crate::input;
+use crate::svt::{MapObject, SVT};
+
+//selection queries, evaluated against the loaded map to pick the objects a range applies to
+//  <source> [where] [<condition>]
+//sources: hits, snaps [<n>/<d> ...], barlines, inh, repeats, tails, ticks, ends
+//conditions are combined with and/or/not and parentheses, from:
+//  any hit filter term (finish, slider, col2, ...), which only hit objects can match
+//  kiai - kiai is active at the object
+//  beat % <m> == <r> (or !=) - beats since the last red line, e.g. beat % 1 == 0 for objects on the beat
+//  near <source> <N>ms - within N ms of an object from another source
+//for example:
+//  hits where finish and kiai and beat % 1 == 0
+//  snaps 1/4 not near hits 20ms
+
+enum Cond {
+  And(Box<Cond>, Box<Cond>),
+  Or(Box<Cond>, Box<Cond>),
+  Not(Box<Cond>),
+  Hit(HitTerm),
+  Kiai,
+  //modulus, whether to check for equality, remainder
+  Beat(f64, bool, f64),
+  //sorted times of the other source, max distance in ms
+  Near(Vec<i32>, i32),
+}
+
+//timing information needed to evaluate conditions at any time
+struct Timeline {
+  red_lines: Vec<(f64, f64)>,
+  kiai: Vec<(i32, bool)>,
+  keys: i32,
+}
+
+struct QueryParser<'a> {
+  svt: &'a SVT,
+  tokens: Vec<String>,
+  pos: usize,
+}
+
+impl SVT {
+  //evaluate a query, returning the matching objects in chronological order
+  pub fn run_query(&self, query: &str) -> Result<Vec<MapObject>> {
+    //make sure operators and parentheses are separate tokens
+    let spaced = query.replace("!=", " != ").replace("==", " == ").replace("%", " % ").replace("(", " ( ").replace(")", " ) ");
+    let mut parser = QueryParser{svt: self, tokens: spaced.split_whitespace().map(|t| t.to_string()).collect(), pos: 0};
+
+    let objs = parser.parse_source()?;
+    if parser.peek() == Some("where") {
+      parser.pos += 1;
+    }
+    let cond = if parser.peek().is_some() { Some(parser.parse_or()?) } else { None };
+    if let Some(token) = parser.peek() {
+      return Err(anyhow!("[query] unexpected {}", token));
+    }
+
+    let timeline = Timeline{
+      red_lines: self.all_objs.iter().filter(|obj| obj.class == 0).map(|obj| (obj.exact_time(), obj.beatlength)).collect(),
+      kiai: self.all_objs.iter().filter(|obj| obj.class <= 1).map(|obj| (obj.time, obj.effects & 1 != 0)).collect(),
+      keys: self.circle_size.round() as i32,
+    };
+
+    let selection: Vec<MapObject> = match cond {
+      Some(cond) => objs.into_iter().filter(|obj| cond.eval(obj, &timeline)).collect(),
+      None => objs,
+    };
+    eprintln!("[query] {} objects selected by {}", selection.len(), query);
+
+    Ok(selection)
+  }
+}
+
+impl<'a> QueryParser<'a> {
+  fn peek(&self) -> Option<&str> {
+    self.tokens.get(self.pos).map(|t| t.as_str())
+  }
+
+  fn next(&mut self) -> Result<String> {
+    let token = self.tokens.get(self.pos).cloned().ok_or(anyhow!("[query] unexpected end of query"))?;
+    self.pos += 1;
+    Ok(token)
+  }
+
+  //<source> [<n>/<d> ...], returns the source's objects in chronological order
+  fn parse_source(&mut self) -> Result<Vec<MapObject>> {
+    let source = self.next()?;
+    let class = match source.as_str() {
+      "hits" => 3,
+      "inh" => 1,
+      "repeats" => 5,
+      "tails" => 6,
+      "ticks" => 7,
+      "ends" => 8,
+      "barlines" => 9,
+      "snaps" => {
+        //snappings can use their own divisors, otherwise the ones loaded with the map
+        let mut divisors = Vec::new();
+        while let Some(token) = self.peek().filter(|t| t.contains("/")) {
+          divisors.push(token.to_string());
+          self.pos += 1;
+        }
+        if divisors.len() > 0 {
+          let divisors = input::parse_divisors(&divisors.join(" ")).context("[query] invalid snapping")?;
+          return Ok(self.svt.generate_snappings(&divisors));
+        }
+        2
+      },
+      _ => return Err(anyhow!("[query] unknown source {}", source)),
+    };
+
+    Ok(self.svt.all_objs.iter().filter(|obj| obj.class == class).cloned().collect())
+  }
+
+  fn parse_or(&mut self) -> Result<Cond> {
+    let mut cond = self.parse_and()?;
+    while self.peek() == Some("or") {
+      self.pos += 1;
+      cond = Cond::Or(Box::new(cond), Box::new(self.parse_and()?));
+    }
+    Ok(cond)
+  }
+
+  fn parse_and(&mut self) -> Result<Cond> {
+    let mut cond = self.parse_not()?;
+    while self.peek() == Some("and") {
+      self.pos += 1;
+      cond = Cond::And(Box::new(cond), Box::new(self.parse_not()?));
+    }
+    Ok(cond)
+  }
+
+  fn parse_not(&mut self) -> Result<Cond> {
+    if self.peek() == Some("not") {
+      self.pos += 1;
+      return Ok(Cond::Not(Box::new(self.parse_not()?)));
+    }
+    self.parse_atom()
+  }
+
+  fn parse_atom(&mut self) -> Result<Cond> {
+    let token = self.next()?;
+    match token.as_str() {
+      "(" => {
+        let cond = self.parse_or()?;
+        if self.next()? != ")" {
+          return Err(anyhow!("[query] missing )"));
+        }
+        Ok(cond)
+      },
+      "kiai" => Ok(Cond::Kiai),
+      "beat" => {
+        if self.next()? != "%" {
+          return Err(anyhow!("[query] expected beat % <m> == <r>"));
+        }
+        let modulus = input::parse_fraction(&self.next()?).context("[query] invalid beat modulus")?;
+        let equal = match self.next()?.as_str() {
+          "==" => true,
+          "!=" => false,
+          op => return Err(anyhow!("[query] unknown operator {}", op)),
+        };
+        let value = input::parse_fraction(&self.next()?).context("[query] invalid beat value")?;
+        if modulus <= 0.0 {
+          return Err(anyhow!("[query] beat modulus should be > 0"));
+        }
+        Ok(Cond::Beat(modulus, equal, value))
+      },
+      "near" => {
+        let mut times: Vec<i32> = self.parse_source()?.iter().map(|obj| obj.time).collect();
+        times.sort();
+        let dist = self.next()?;
+        let ms = dist.strip_suffix("ms").and_then(|ms| ms.parse::<i32>().ok()).ok_or(anyhow!("[query] expected a distance in ms, found {}", dist))?;
+        Ok(Cond::Near(times, ms))
+      },
+      _ => {
+        //a leading ! is shorthand for not
+        match token.strip_prefix("!") {
+          Some(term) => Ok(Cond::Not(Box::new(Cond::Hit(filter::parse_term(term)?)))),
+          None => Ok(Cond::Hit(filter::parse_term(&token)?)),
+        }
+      },
+    }
+  }
+}
+
+impl Cond {
+  fn eval(&self, obj: &MapObject, timeline: &Timeline) -> bool {
+    match self {
+      Cond::And(a, b) => a.eval(obj, timeline) && b.eval(obj, timeline),
+      Cond::Or(a, b) => a.eval(obj, timeline) || b.eval(obj, timeline),
+      Cond::Not(a) => !a.eval(obj, timeline),
+      Cond::Hit(term) => obj.class == 3 && filter::term_matches(*term, obj, timeline.keys),
+      Cond::Kiai => {
+        let idx = timeline.kiai.partition_point(|(t, _)| *t <= obj.time);
+        idx > 0 && timeline.kiai[idx - 1].1
+      },
+      Cond::Beat(modulus, equal, value) => {
+        //objects on a red line at a decimal time are truncated to the ms it falls in
+        let idx = timeline.red_lines.partition_point(|(t, _)| *t < obj.time as f64 + 1.0);
+        if idx == 0 {
+          return false;
+        }
+        let (red_time, beatlength) = timeline.red_lines[idx - 1];
+
+        //allow 2ms of rounding either way, measured in beats
+        let tolerance = 2.0 / beatlength;
+        let rem = ((obj.time as f64 - red_time) / beatlength - value).rem_euclid(*modulus);
+        let on_value = rem <= tolerance || modulus - rem <= tolerance;
+        on_value == *equal
+      },
+      Cond::Near(times, ms) => {
+        let idx = times.partition_point(|t| *t < obj.time - ms);
+        idx < times.len() && times[idx] <= obj.time + ms
+      },
+    }
+  }
+}
@@ -1,10 +1,18 @@
 use anyhow::{anyhow, Result, Context};
 
 use std::cmp;
+use std::collections::{HashMap, HashSet};
 use std::fs::{self, File};
-use std::io::{self, BufRead, Write};
-use std::path::Path;
-
+use std::io::Write;
+
+use crate::backup;
+use crate::diff::{self, TimingDiff};
+use crate::filter::HitFilter;
+use crate::history::History;
+use crate::input::{self, InputRange};
+use crate::optimize;
+use crate::osufile::OsuFile;
+use crate::preview;
 use crate::ui;
 
 //TODO - add enum and resolve MapObject Clone derive
@@ -17,17 +25,28 @@ enum MapObjectClass {
 }
 */
 
+//classes: 0 uni, 1 inh, 2 snapping, 3 hit (incl. slider heads), 4 new tool point,
+//5 slider repeat, 6 slider tail, 7 slider tick, 8 spinner/hold end, 9 barline
 #[derive(Clone, Debug, Default)]
 pub struct MapObject {
   pub class: i32,
   pub time: i32,
-  pub beatlength: f32,
+  //timing points can have decimal times, time is the ms they fall in and this is the rest
+  //objects on a red line at a decimal time are truncated to that same ms by the editor
+  pub time_fraction: f64,
+  pub beatlength: f64,
   pub meter: i32,
   pub sampleset: i32,
   pub sampleindex: i32,
   pub volume: i32,
   pub uninherited: i32,
   pub effects: i32,
+  pub hit_type: i32,
+  pub hitsound: i32,
+  pub x: i32,
+  pub end_time: i32,
+  pub slides: i32,
+  pub length: f64,
   pub data: String,
 }
 
@@ -35,30 +54,109 @@ pub struct MapObject {
 pub struct SVT {
   pub all_objs: Vec<MapObject>,
   pub new_objs: Vec<MapObject>,
+  pub bookmarks: Vec<i32>,
+  pub slider_multiplier: f64,
+  pub slider_tick_rate: f64,
+  pub circle_size: f64,
+  pub default_sampleset: i32,
+  pub changed_sliders: Vec<i32>,
+  pub snapping_end: Option<i32>,
+  pub precision: usize,
+  pub skipped_lines: usize,
+  pub timing_defaults: TimingDefaults,
+  pub merge_report: Vec<String>,
+  pub optimized_lines: usize,
+  pub filename: String,
+  pub clear_ranges: Vec<InputRange>,
+  pub tool_lines: Option<HashSet<String>>,
+}
+
+//how a tool point is merged with existing inherited lines within min spacing of it
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MergePolicy {
+  //drop the existing lines
+  Replace,
+  //drop the tool point
+  KeepExisting,
+  //multiply the tool point's sv by the existing sv
+  Multiply,
+  //take the existing sampleset, sample index and volume
+  KeepSounds,
+  //take the existing kiai/effects
+  KeepEffects,
+}
+
+//values osu! fills in for fields missing from timing points, older file format versions write fewer than 8 fields
+//the sampleset and volume come from the map's [General] section
+#[derive(Clone, Copy, Debug)]
+pub struct TimingDefaults {
+  pub sampleset: i32,
+  pub volume: i32,
+}
+
+impl Default for TimingDefaults {
+  //sampleset 0 is the map's default sampleset
+  fn default() -> Self { TimingDefaults {
+      sampleset: 0,
+      volume: 100,
+    }
+  }
+}
+
+impl MapObject {
+  pub fn exact_time(&self) -> f64 {
+    self.time as f64 + self.time_fraction
+  }
+}
+
+impl MergePolicy {
+  pub fn parse(text: &str) -> Result<MergePolicy> {
+    match text {
+      "replace" => Ok(MergePolicy::Replace),
+      "keep existing" => Ok(MergePolicy::KeepExisting),
+      "multiply" => Ok(MergePolicy::Multiply),
+      "keep sounds" => Ok(MergePolicy::KeepSounds),
+      "keep effects" => Ok(MergePolicy::KeepEffects),
+      _ => Err(anyhow!("[write] unknown merge policy {}", text)),
+    }
+  }
 }
 
 impl SVT {
   //apply function between two points using options from opt
-  pub fn apply_two_point_fn(&mut self, start_line: &str, end_line: &str, opt: &ui::AppOptions) -> Result<()> {
+  pub fn apply_two_point_fn(&mut self, range: &InputRange, opt: &ui::AppOptions) -> Result<()> {
+    //clear ranges remove existing lines when merging, which lines were placed by svt is only known from the map's history
+    if let Some(clear) = range.clear {
+      if !clear.all && self.tool_lines.is_none() {
+        self.tool_lines = Some(History::load(&self.filename).and_then(|history| history.tool_lines())
+          .context("[apply] couldn't read history to find the lines svt placed, use clear all to clear every inh. line instead")?);
+      }
+      self.clear_ranges.push(range.clone());
+      return Ok(());
+    }
     
     //only validate these text fields when the corresponding modes are enabled
     let pol_exp = if opt.pol_sv {
-      opt.pol_exp.parse::<f32>().context("[apply] invalid exponent")?
+      opt.pol_exp.parse::<f64>().context("[apply] invalid exponent")?
     } else {
       1.0
     };
     let flat_sv = if opt.flat_sv && !opt.flat_scaling {
-      opt.flat_change.parse::<f32>().context("[apply] invalid flat sv")?
+      opt.flat_change.parse::<f64>().context("[apply] invalid flat sv")?
     } else {
       0.0
     };
 
     let flat_sv_scaling = if opt.flat_sv && opt.flat_scaling {
-      opt.flat_scaling_change.parse::<f32>().context("[apply] invalid flat sv scaling")?
+      opt.flat_scaling_change.parse::<f64>().context("[apply] invalid flat sv scaling")?
     } else {
       1.0
     };
 
+    let hit_filter = HitFilter::parse(&opt.hit_filter).context("[apply] invalid hit filter")?;
+
+    let keys = self.circle_size.round() as i32;
+
     let t_off = opt.offset.parse::<i32>().context("[apply] invalid offset")?;
     let t_buf = opt.buffer.parse::<i32>().context("[apply] invalid buffer")?;
 
@@ -69,8 +167,8 @@ impl SVT {
       return Err(anyhow!("[apply] nothing to apply (sv, vol)"));
     }
 
-    let start_obj = create_map_object(start_line.to_string(), true).context("[apply] timing point format error")?;
-    let end_obj = create_map_object(end_line.to_string(), true).context("[apply] timing point format error")?;
+    let start_obj = &range.start;
+    let end_obj = &range.end;
 
     //TODO although all_objs is sorted at this point, could ensure that it is sorted
     //self.all_objs.sort_by_key(|k| (k.time, k.class));
@@ -102,7 +200,7 @@ impl SVT {
     };
 
     //debug print
-    println!("[apply] t:{}->{} raw sv:{}->{} vol:{}->{}", start_obj.time, end_obj.time, s_sv_raw, e_sv_raw, start_obj.volume, end_obj.volume);
+    eprintln!("[apply] t:{}->{} raw sv:{}->{} vol:{}->{}", start_obj.time, end_obj.time, s_sv_raw, e_sv_raw, start_obj.volume, end_obj.volume);
 
     //validation on input values
     if start_obj.time > end_obj.time {
@@ -115,17 +213,17 @@ impl SVT {
       return Err(anyhow!("[apply] invalid volumes (vol < 0 or vol > 100)"));
     }
     
-    //compute change per time tick
-    let t_diff = end_obj.time - start_obj.time;
+    //compute change per time tick, a single selected object has no duration to interpolate over
+    let t_diff = cmp::max(1, end_obj.time - start_obj.time);
     let sv_diff = e_sv_raw - s_sv_raw;
     let sv_ratio = e_sv_raw / s_sv_raw;
     let vol_diff = end_obj.volume - start_obj.volume;
-    let sv_per_ms = sv_diff / t_diff as f32;
-    let vol_per_ms = vol_diff as f32 / t_diff as f32;
+    let sv_per_ms = sv_diff / t_diff as f64;
+    let vol_per_ms = vol_diff as f64 / t_diff as f64;
 
     //TODO update these with the real default values
     //init with something here to prevent catastrophic failure before first uninherited line
-    let mut last_uni_time = 0;
+    let mut last_uni_time = 0.0;
     let mut bpm = 160.0;
     let mut beatlength = -100.0;
     let mut meter = 4;
@@ -134,9 +232,30 @@ impl SVT {
     let mut volume = 100;
     let mut effects = 0;
 
-    let mut kiai_change_time = 0;
+    let mut kiai_change_time = 0.0;
+
+    //with an explicit selection, go through the timing points plus the selected objects instead of the whole map
+    //selected timing points are only walked once, as selected
+    let mut objs: Vec<(&MapObject, bool)> = Vec::new();
+    match &range.selection {
+      Some(selection) => {
+        let selected_lines: HashSet<(i32, &str)> = selection.iter().filter(|obj| obj.class <= 1).map(|obj| (obj.class, obj.data.as_str())).collect();
+        objs.extend(self.all_objs.iter().filter(|obj| obj.class <= 1 && !selected_lines.contains(&(obj.class, obj.data.as_str()))).map(|obj| (obj, false)));
+        objs.extend(selection.iter().map(|obj| (obj, true)));
+        objs.sort_by_key(|(obj, selected)| (obj.time, obj.class, *selected));
+      },
+      None => match &range.snappings {
+        //snappings from the range's own divisors replace the ones loaded with the map
+        Some(snappings) => {
+          objs.extend(self.all_objs.iter().filter(|obj| obj.class != 2).map(|obj| (obj, false)));
+          objs.extend(snappings.iter().map(|obj| (obj, false)));
+          objs.sort_by_key(|(obj, selected)| (obj.time, obj.class, *selected));
+        },
+        None => objs.extend(self.all_objs.iter().map(|obj| (obj, false))),
+      },
+    }
 
-    for obj in self.all_objs.iter() {
+    for (obj, selected) in objs.into_iter() {
       //only consider timing points for flat sv
       if opt.flat_sv && obj.class > 1 {
         continue;
@@ -148,10 +267,10 @@ impl SVT {
 
         //check whether kiai change occurs
         if obj.effects & 1 != effects & 1 {
-          kiai_change_time = obj.time;
+          kiai_change_time = obj.exact_time();
         }
 
-        last_uni_time = obj.time;
+        last_uni_time = obj.exact_time();
 
         bpm = 60000.0 / obj.beatlength;
         meter = obj.meter;
@@ -167,7 +286,7 @@ impl SVT {
 
         //check whether kiai change occurs
         if obj.effects & 1 != effects & 1 {
-          kiai_change_time = obj.time;
+          kiai_change_time = obj.exact_time();
         }
 
         beatlength = obj.beatlength;
@@ -179,20 +298,23 @@ impl SVT {
 
       //perform general calculations here for inher, snappings, hitobjects
       let obj_time = obj.time;
-      if obj_time >= start_obj.time - t_buf && obj_time <= end_obj.time + t_buf {
+      let in_range = obj_time >= start_obj.time - t_buf && obj_time <= end_obj.time + t_buf;
+      if in_range && (selected || range.selection.is_none()) {
         //ensure time is set both after any uninherited points or kiai time changes within offset window
-        let new_t = cmp::max(cmp::max(obj_time + t_off, last_uni_time), kiai_change_time);
+        //red lines and kiai changes can be at decimal times, which the new point has to keep to stay after them
+        let new_exact_t = f64::max(f64::max(obj.exact_time() + t_off as f64, last_uni_time), kiai_change_time);
+        let new_t = new_exact_t.floor() as i32;
         let new_sv = if opt.lin_sv {
           //linear
-          s_sv_raw + (obj_time - start_obj.time) as f32 * sv_per_ms
+          s_sv_raw + (obj_time - start_obj.time) as f64 * sv_per_ms
         } else if opt.exp_sv {
           //exponential
-          s_sv_raw * f32::exp((obj_time - start_obj.time) as f32 * f32::ln(sv_ratio) / t_diff as f32)
+          s_sv_raw * f64::exp((obj_time - start_obj.time) as f64 * f64::ln(sv_ratio) / t_diff as f64)
         } else if opt.pol_sv {
           //polynomial
-          s_sv_raw + sv_diff * f32::powf(cmp::max(0, obj_time - start_obj.time) as f32 / t_diff as f32, pol_exp)
+          s_sv_raw + sv_diff * f64::powf(cmp::max(0, obj_time - start_obj.time) as f64 / t_diff as f64, pol_exp)
         } else if opt.sin_sv {
-          s_sv_raw + sv_diff * (1 as f32 - f32::cos(std::f32::consts::PI * (obj_time - start_obj.time) as f32 / t_diff as f32)) / 2 as f32
+          s_sv_raw + sv_diff * (1 as f64 - f64::cos(std::f64::consts::PI * (obj_time - start_obj.time) as f64 / t_diff as f64)) / 2 as f64
         } else if opt.flat_sv {
           //flat
           if opt.flat_scaling {
@@ -205,52 +327,52 @@ impl SVT {
         };
 
         let new_b = -100.0 / (new_sv / bpm);
-        let new_vol = ((start_obj.volume as f32 + (obj_time - start_obj.time) as f32 * vol_per_ms)).round() as u32;
-        let new_point = match (sv_change_bool, opt.vol) {
+        let new_vol = ((start_obj.volume as f64 + (obj_time - start_obj.time) as f64 * vol_per_ms)).round() as u32;
+        let (out_b, out_vol) = match (sv_change_bool, opt.vol) {
           //sv and vol
-          (true, true) => {
-            format!("{},{},{},{},{},{},{},{}", new_t, new_b, meter, sample_set, sample_index, new_vol, 0, effects)
-          },
+          (true, true) => (new_b, new_vol as i32),
           //sv and no vol
-          (true, false) => {
-            format!("{},{},{},{},{},{},{},{}", new_t, new_b, meter, sample_set, sample_index, volume, 0, effects)
-          },
+          (true, false) => (new_b, volume),
           //no sv and vol
-          (false, true) => {
-            format!("{},{},{},{},{},{},{},{}", new_t, beatlength, meter, sample_set, sample_index, new_vol, 0, effects)
-          },
+          (false, true) => (beatlength, new_vol as i32),
           //no sv, no vol - should not reach this point
+          _ => (beatlength, volume),
+        };
+        //keep the fields alongside the line so the new points can be inspected when merging
+        let mut new_obj = MapObject{
+          class: 4,
+          time: new_t,
+          time_fraction: new_exact_t - new_t as f64,
+          beatlength: out_b,
+          meter: meter,
+          sampleset: sample_set,
+          sampleindex: sample_index,
+          volume: out_vol,
+          uninherited: 0,
+          effects: effects,
+          ..Default::default()
+        };
+        new_obj.data = format_timing_point(&new_obj, self.precision);
+
+        //selected objects are always changed, otherwise go by the target checkboxes
+        let (target, tag) = match obj.class {
+          1 => (opt.inh_lines || opt.flat_sv, "inh"), //inherited line
+          2 => (opt.snappings, "snp"), //snapping
+          3 => (opt.hits && hit_filter.matches(obj, keys), "hit"), //hitobject, optionally filtered by type/hitsound/column
+          5 => (opt.slider_repeats, "rep"), //slider repeat
+          6 => (opt.slider_tails, "tal"), //slider tail
+          7 => (opt.slider_ticks, "tck"), //slider tick
+          8 => (opt.spinner_ends, "end"), //spinner/hold end
+          9 => (opt.barlines, "bar"), //barline
           _ => {
-            format!("")
+            eprintln!("[apply] unknown class {}", obj.class);
+            (false, "")
           },
         };
 
-        match obj.class {
-          0 => {println!("[apply] shouldn't get here, class 1");}, //uninherited line
-          1 => {
-            //inherited line
-            if opt.inh_lines || opt.flat_sv {
-              println!("[new] inh {}", new_point);
-              self.new_objs.push(MapObject{time: new_t, class: 4, data: new_point, ..Default::default()});
-            }
-          },
-          2 => {
-            //snapping
-            if opt.snappings {
-              println!("[new] snp {}", new_point);
-              self.new_objs.push(MapObject{time: new_t, class: 4, data: new_point, ..Default::default()});
-            }
-          },
-          3 => {
-            //hitobject
-            if opt.hits {
-              println!("[new] hit {}", new_point);
-              self.new_objs.push(MapObject{time: new_t, class: 4, data: new_point, ..Default::default()});
-            }
-          },
-          _ => {
-            println!("[apply] unknown class {}", obj.class);
-          }
+        if selected || target {
+          eprintln!("[new] {} {}", tag, new_obj.data);
+          self.new_objs.push(new_obj);
         }
       }
     }
@@ -261,107 +383,282 @@ impl SVT {
   //clear all old map objects, load in a new file and repopulate with latest saved state
   //populates all_objs with timing points (both uni and inh), hit objects, and snappings
   pub fn load_osu(&mut self, filename: &String, opt: &ui::AppOptions) -> Result<()> {
-    let mut bool_timing = false;
-    let mut bool_hit = false;
-
-    let snapping_numer = opt.snapping_numer.parse::<f32>().context("[load] invalid snapping numer")?;
-    let snapping_denom = opt.snapping_denom.parse::<f32>().context("[load] invalid snapping denom")?;
+    let divisors = input::parse_divisors(&opt.snapping).context("[load] invalid snapping")?;
 
-    if snapping_denom <= 0.0 {
-      return Err(anyhow!("[load] snapping denom should be > 0"));
+    //f64 holds at most 17 significant digits
+    if opt.precision < 1 || opt.precision > 17 {
+      return Err(anyhow!("[load] precision must be between 1 and 17 digits"));
     }
-
-    if snapping_numer <= 0.0 {
-      return Err(anyhow!("[load] snapping numer should be > 0"));
-    }
-
-    //TODO figure out better way to init this
-    let mut snap_time: f32 = 1000000.0;
-    let mut snap_inc: f32 = 1000000.0;
-    let mut beat_inc: f32;
+    self.precision = opt.precision as usize;
 
     self.all_objs.clear();
     self.new_objs.clear();
+    self.bookmarks.clear();
+    self.clear_ranges.clear();
+    self.tool_lines = None;
+    self.filename = filename.clone();
+
+    //osu! defaults, in case the [Difficulty] section leaves them out
+    self.slider_multiplier = 1.4;
+    self.slider_tick_rate = 1.0;
+    self.circle_size = 5.0;
+    self.default_sampleset = 1;
+    self.skipped_lines = 0;
+    self.timing_defaults = TimingDefaults{sampleset: 1, volume: 100};
+    let mut short_points = 0;
+
+    //older format versions (v3-v6) leave fields off the end of timing points, osu! fills them the same way whatever the version
+    //times are kept as written, so maps that osu! offsets for their version stay in sync with their own objects
+    let map_file = OsuFile::read(filename).context("[load] couldn't read map")?;
+
+    for line in map_file.lines.iter() {
+      // we mostly care about the TimingPoints and HitObjects headers/sections
+      if line.is_header() {
+        if line.section == "TimingPoints" || line.section == "HitObjects" {
+          eprintln!("[load] found [{}], reading", line.section);
+        }
+        continue;
+      }
 
-    // read file line by line
-    if let Ok(lines) = read_lines(&filename) {
-      for line in lines {
-        if let Ok(s) = line {
-          // we only care about the TimingPoints and HitObjects headers/sections
-          match s.as_str() {
-            "[General]" | "[Editor]" | "[Metadata]" | "[Difficulty]" | "[Events]" | "[Colours]" => {
-              bool_timing = false;
-              bool_hit = false;
-            },
-            "[TimingPoints]" => {
-              bool_timing = true;
-              bool_hit = false;
-              println!("[load] found [TimingPoints], reading");
-            },
-            "[HitObjects]" => {
-              bool_timing = false;
-              bool_hit = true;
-              println!("[load] found [HitObjects], reading");
-            },
-            _ => {
-              if bool_timing {
-                if let Ok(map_obj) = create_map_object(s, true) {
-                  //add snappings since last timing point
-                  while snap_time + snap_inc < map_obj.time as f32 {
-                    snap_time += snap_inc;
-                    self.all_objs.push(MapObject{time: snap_time.round() as i32, class: 2, data: String::from(""), ..Default::default()});
-                  }
-
-                  //use uninherited point properties to calculate snapping times
-                  if map_obj.uninherited == 1 {
-                    //set snapping counter
-                    snap_time = map_obj.time as f32;
-                    beat_inc = map_obj.beatlength as f32;
-                    snap_inc = beat_inc * snapping_numer / snapping_denom;
-
-                    /*
-                    //add current barline if not skipping barline (skip if effects is set to 8)
-                    if map_obj.effects & 8 != 8 {
-                      self.all_objs.push(MapObject{time: snap_time as i32, class: 2, data: String::from(""), ..Default::default()});
-                    }
-                    */
-
-                    //add current snapping
-                    self.all_objs.push(MapObject{time: snap_time as i32, class: 2, data: String::from(""), ..Default::default()});
-                  }
-
-                  //add timing point
-                  self.all_objs.push(map_obj);
-                }
-              } else if bool_hit {
-                if let Ok(map_obj) = create_map_object(s, false) {
-                  self.all_objs.push(map_obj);
-                }
-              } else {
-                continue;
+      let s = match line.content() {
+        Some(s) => s,
+        None => continue,
+      };
+
+      if line.section == "General" {
+        //objects without a sampleset fall back on the map's default one
+        match line.key_value() {
+          Some(("SampleSet", value)) => {
+            self.default_sampleset = match value {
+              "Soft" => 2,
+              "Drum" => 3,
+              _ => 1,
+            };
+            self.timing_defaults.sampleset = self.default_sampleset;
+          },
+          //timing points without a volume use the map's sample volume
+          Some(("SampleVolume", value)) => self.timing_defaults.volume = value.parse::<i32>().context("[load] invalid SampleVolume")?,
+          _ => {},
+        }
+      } else if line.section == "Editor" {
+        //bookmarks are used as anchors in the input grammar
+        if let Some(("Bookmarks", value)) = line.key_value() {
+          self.bookmarks = value.split(",").filter_map(|b| b.trim().parse::<i32>().ok()).collect();
+          self.bookmarks.sort();
+        }
+      } else if line.section == "Difficulty" {
+        //slider settings are needed to compute slider end times
+        match line.key_value() {
+          Some(("SliderMultiplier", value)) => self.slider_multiplier = value.parse::<f64>().context("[load] invalid SliderMultiplier")?,
+          Some(("SliderTickRate", value)) => self.slider_tick_rate = value.parse::<f64>().context("[load] invalid SliderTickRate")?,
+          Some(("CircleSize", value)) => self.circle_size = value.parse::<f64>().context("[load] invalid CircleSize")?,
+          _ => {},
+        }
+      } else if line.section == "TimingPoints" {
+        match create_timing_point(s.to_string(), &self.timing_defaults) {
+          Ok(mut map_obj) => {
+            //short lines can be written back with every field filled in
+            if s.split(",").count() < 8 {
+              short_points += 1;
+              if opt.upgrade_timing_points {
+                map_obj.data = format_timing_point(&map_obj, self.precision);
               }
-            },
-          }
+            }
+            //add timing point
+            self.all_objs.push(map_obj);
+          },
+          Err(err) => {
+            eprintln!("[load] skipped timing point {} ({})", s, err);
+            self.skipped_lines += 1;
+          },
+        }
+      } else if line.section == "HitObjects" {
+        match create_map_object(s.to_string(), false) {
+          Ok(map_obj) => self.all_objs.push(map_obj),
+          Err(err) => {
+            eprintln!("[load] skipped hit object {} ({})", s, err);
+            self.skipped_lines += 1;
+          },
         }
       }
     }
 
-    //load another 25% of snappings in case user wants to fade out or something lol
-    let end_snap_time = snap_time * 1.25;
-    while snap_time + snap_inc < end_snap_time {
-      snap_time += snap_inc;
-      self.all_objs.push(MapObject{time: snap_time.round() as i32, class: 2, data: String::from(""), ..Default::default()});
+    if short_points > 0 {
+      eprintln!("[load] {} timing points without every field{}", short_points, if opt.upgrade_timing_points { ", upgrading" } else { "" });
     }
 
+    //slider ends depend on the sv active at each slider, so timing points need to be in order first
+    self.all_objs.sort_by_key(|k| (k.time, k.class));
+    self.add_hit_targets();
+
+    //the end of the map can be given explicitly, e.g. to keep snappings going through a fade out after the last object
+    self.snapping_end = if opt.snapping_end.trim().len() > 0 {
+      Some(self.parse_time(opt.snapping_end.trim(), &HashMap::new()).context("[load] invalid snapping end")?)
+    } else {
+      None
+    };
+    let snappings = self.generate_snappings(&divisors);
+    self.all_objs.extend(snappings);
+    let barlines = self.generate_barlines();
+    self.all_objs.extend(barlines);
     self.all_objs.sort_by_key(|k| (k.time, k.class));
 
     Ok(())
   }
 
-  //write the current output points to the destination file, using the input file as a template for everything except timing points
-  pub fn write_output_points(&mut self, min_spacing_text: String, in_filename: String, out_filename: String, preview: bool) -> Result<usize> {
-    //don't write anything if no new objects
-    if self.new_objs.len() == 0 {
+  //red lines paired with the time their section ends, which is the next red line or the end of the map
+  //the map ends at the explicit snapping end if set, otherwise at the end of the last hit object (or the last timing point without any)
+  fn red_line_sections(&self) -> Vec<(&MapObject, f64)> {
+    let red_lines: Vec<&MapObject> = self.all_objs.iter().filter(|obj| obj.class == 0).collect();
+    let map_end = match self.snapping_end {
+      Some(end_time) => end_time,
+      None => self.all_objs.iter().filter(|obj| obj.class == 3).map(|obj| cmp::max(obj.time, obj.end_time)).max()
+        .or(self.all_objs.iter().filter(|obj| obj.class <= 1).map(|obj| obj.time).max())
+        .unwrap_or(0),
+    };
+
+    red_lines.iter().enumerate().map(|(i, red_line)| {
+      let end_time = match red_lines.get(i + 1) {
+        Some(next) => cmp::min(next.time, map_end + 1),
+        None => map_end + 1,
+      };
+      (*red_line, end_time as f64)
+    }).collect()
+  }
+
+  //snapping times for a set of numer/denom beat divisors, restarting at each red line
+  //ticks shared between divisors (e.g. 1/4 and 1/3 on the beat) are only added once
+  pub fn generate_snappings(&self, divisors: &[(f64, f64)]) -> Vec<MapObject> {
+    let mut times = Vec::new();
+
+    for (red_line, end_time) in self.red_line_sections() {
+      if red_line.beatlength <= 0.0 {
+        continue;
+      }
+
+      for (numer, denom) in divisors.iter() {
+        //steps under 1ms can't place distinct snappings, and one that doesn't move would never reach the end
+        if !(red_line.beatlength * numer / denom >= 1.0) {
+          eprintln!("[snap] skipping {}/{} from the red line at {}, steps are under 1ms", numer, denom, format_timestamp(red_line.time));
+          continue;
+        }
+
+        let mut k = 0;
+        let mut last_time = None;
+        loop {
+          let snap_time = beat_time(red_line.exact_time(), red_line.beatlength, k as f64 * numer, *denom);
+          if snap_time as f64 >= end_time || last_time.map_or(false, |t| snap_time <= t) {
+            break;
+          }
+          times.push(snap_time);
+          last_time = Some(snap_time);
+          k += 1;
+        }
+      }
+    }
+
+    times.sort();
+    times.dedup();
+    times.into_iter().map(|t| MapObject{time: t, class: 2, data: String::from(""), ..Default::default()}).collect()
+  }
+
+  //barline times, one every meter beats from each red line
+  //the omit first barline effect (8) only skips the barline on the red line itself
+  pub fn generate_barlines(&self) -> Vec<MapObject> {
+    let mut barlines = Vec::new();
+
+    for (red_line, end_time) in self.red_line_sections() {
+      //a measure under 1ms would never reach the end either
+      if red_line.meter <= 0 || !(red_line.beatlength * red_line.meter as f64 >= 1.0) {
+        continue;
+      }
+
+      let mut k = if red_line.effects & 8 != 0 { 1 } else { 0 };
+      loop {
+        let bar_time = beat_time(red_line.exact_time(), red_line.beatlength, (k * red_line.meter) as f64, 1.0);
+        if bar_time as f64 >= end_time {
+          break;
+        }
+        barlines.push(MapObject{time: bar_time, class: 9, data: String::from(""), ..Default::default()});
+        k += 1;
+      }
+    }
+
+    barlines
+  }
+
+  //compute slider/spinner/hold end times and add slider repeats, tails, ticks and spinner/hold ends as map objects
+  //also resolves the sampleset/index of hit objects that inherit them from timing points
+  fn add_hit_targets(&mut self) {
+    let mut targets = Vec::new();
+    let mut beatlength = 0.0;
+    let mut sv = 1.0;
+    let mut sampleset = 0;
+    let mut sampleindex = 0;
+
+    for obj in self.all_objs.iter_mut() {
+      if obj.class == 0 {
+        beatlength = obj.beatlength;
+        sv = 1.0;
+        sampleset = obj.sampleset;
+        sampleindex = obj.sampleindex;
+      } else if obj.class == 1 {
+        //osu! clamps sv to [0.1, 10] for slider velocity
+        sv = f64::max(0.1, f64::min(10.0, -100.0 / obj.beatlength));
+        sampleset = obj.sampleset;
+        sampleindex = obj.sampleindex;
+      } else if obj.class == 3 {
+        if obj.sampleset == 0 {
+          obj.sampleset = if sampleset > 0 { sampleset } else { self.default_sampleset };
+        }
+        if obj.sampleindex == 0 {
+          obj.sampleindex = sampleindex;
+        }
+
+        if obj.hit_type & 2 != 0 {
+          if beatlength <= 0.0 || obj.slides <= 0 {
+            continue;
+          }
+
+          //slider span duration follows from its pixel length and velocity at the slider head
+          let span = obj.length / (self.slider_multiplier * 100.0 * sv) * beatlength;
+          let tick = beatlength / self.slider_tick_rate;
+
+          for i in 0..obj.slides {
+            let span_start = obj.time as f64 + span * i as f64;
+
+            //ticks are placed from the start of each span, mirrored on reverse spans, and skipped near the end
+            let mut k = 1;
+            while k as f64 * tick < span - 10.0 {
+              let tick_time = if i % 2 == 0 { span_start + k as f64 * tick } else { span_start + span - k as f64 * tick };
+              targets.push(MapObject{class: 7, time: tick_time.round() as i32, data: String::from(""), ..Default::default()});
+              k += 1;
+            }
+
+            if i < obj.slides - 1 {
+              targets.push(MapObject{class: 5, time: (span_start + span).round() as i32, data: String::from(""), ..Default::default()});
+            }
+          }
+
+          obj.end_time = (obj.time as f64 + span * obj.slides as f64).round() as i32;
+          targets.push(MapObject{class: 6, time: obj.end_time, data: String::from(""), ..Default::default()});
+        } else if obj.hit_type & 8 != 0 || obj.hit_type & 128 != 0 {
+          //spinner or mania hold, end time is part of the object
+          targets.push(MapObject{class: 8, time: obj.end_time, data: String::from(""), ..Default::default()});
+        }
+      }
+    }
+
+    self.all_objs.extend(targets);
+  }
+
+  //merge the new points into the current timing points, returning the timing points to write out,
+  //rewritten slider lines and the number of new points applied
+  //volume_changed is whether the tool points carry new volumes or the map's
+  fn merge_output_points(&mut self, min_spacing_text: String, merge_policy_text: String, fix_sliders: bool, optimize: bool, volume_changed: bool) -> Result<(Vec<MapObject>, HashMap<String, String>, usize)> {
+    //don't write anything if no new objects, unless only clearing or optimizing
+    let only_removing = self.clear_ranges.len() > 0 || optimize;
+    if self.new_objs.len() == 0 && !only_removing {
       return Err(anyhow!("[write] no new objects to apply"));
     }
 
@@ -370,11 +667,12 @@ impl SVT {
     if min_spacing < 0 || min_spacing > 1000 {
       return Err(anyhow!("[write] min spacing cannot be negative or excessively high"));
     }
+
+    let merge_policy = MergePolicy::parse(&merge_policy_text)?;
     
-    //sort new objects in chronological order
-    //TODO inherited vs uninherited should not apply here, these are the new points
-    self.new_objs.sort_by_key(|k| (k.time, k.class));
-    self.new_objs.dedup_by_key(|k| (k.time, k.class));
+    //sort new objects in chronological order, points can sit at a decimal red line's exact time
+    self.new_objs.sort_by(cmp_lines);
+    self.new_objs.dedup_by(|a, b| a.exact_time() == b.exact_time());
 
     //build up a vector with all old and new points sorted in chronological, then uninherited > inherited order
 
@@ -386,174 +684,748 @@ impl SVT {
     let mut svt_objs: Vec<MapObject> = Vec::new();
     let mut out_objs: Vec<MapObject> = Vec::new();
     
-    //remove closely spaced svt points
-    let mut last_obj_time = -1000;
+    let old_lines = self.cleared_lines();
+    let red_times: Vec<f64> = old_lines.iter().filter(|obj| obj.uninherited == 1).map(|obj| obj.exact_time()).collect();
+
+    //remove closely spaced svt points, a red line in between resets sv so points on either side of it don't conflict
+    let mut last_obj_time = -1000.0;
     for obj in self.new_objs.iter() {
-      if obj.time - last_obj_time > min_spacing {
+      let red_between = red_times.iter().any(|t| *t > last_obj_time && *t <= obj.exact_time());
+      if obj.exact_time() - last_obj_time > min_spacing as f64 || red_between {
         svt_objs.push(obj.clone());
-        last_obj_time = obj.time;
+        last_obj_time = obj.exact_time();
       }
     }
 
-    out_objs.extend(svt_objs.iter().cloned());
+    //tool points get the sounds and effects of the original map at their own time, which can be before the object
+    //they were placed for when offset, volume too unless it's being changed
+    for obj in svt_objs.iter_mut() {
+      if let Some(old_line) = line_at(old_lines.iter(), obj.exact_time()) {
+        obj.sampleset = old_line.sampleset;
+        obj.sampleindex = old_line.sampleindex;
+        obj.effects = old_line.effects;
+        if !volume_changed {
+          obj.volume = old_line.volume;
+        }
+        obj.data = format_timing_point(obj, self.precision);
+      }
+    }
 
-    let mut svt_objs_iter = svt_objs.iter();
+    let mut svt_objs_iter = svt_objs.iter().enumerate();
 
     //new objs and svt_objs should not be len 0 unlness min spacing is set to a ridiculous value
     let mut svt_obj = svt_objs_iter.next();
-    if svt_obj.is_none() {
+    if svt_obj.is_none() && !only_removing {
       return Err(anyhow!("[write] no new objects to apply"));
     }
-    last_obj_time = svt_obj.unwrap().time;
+    last_obj_time = svt_obj.map_or(last_obj_time, |(_, obj)| obj.exact_time());
 
-    for obj in self.all_objs.iter() {
+    //existing inherited lines within min_spacing of each tool point
+    let mut conflicts: Vec<Vec<&MapObject>> = vec![Vec::new(); svt_objs.len()];
+
+    for obj in old_lines.iter() {
       //uninherited/inherited lines
       if obj.class == 0 {
         out_objs.push(obj.clone());
       } else if obj.class == 1 {
-        //remove non-tool points around tool points
-        while obj.time - last_obj_time > min_spacing {
+        //find the tool point around this point
+        while obj.exact_time() - last_obj_time > min_spacing as f64 {
           svt_obj = svt_objs_iter.next();
           if svt_obj.is_none() {
             break;
           }
-          last_obj_time = svt_obj.unwrap().time;
+          last_obj_time = svt_obj.unwrap().1.exact_time();
         }
 
-        //only add the point if it is outside min_spacing from tool point
-        if i32::abs(obj.time - last_obj_time) > min_spacing {
-          out_objs.push(obj.clone());
+        //only add the point right away if it is outside min_spacing from tool point
+        match svt_obj {
+          Some((i, _)) if f64::abs(obj.exact_time() - last_obj_time) <= min_spacing as f64 => conflicts[i].push(obj),
+          _ => out_objs.push(obj.clone()),
         }
       }
     }
 
+    //resolve each tool point against the existing lines it conflicts with
+    self.merge_report.clear();
+    let mut applied_count = 0;
+    let mut dropped_objs: Vec<&MapObject> = Vec::new();
+    for (svt_obj, old_objs) in svt_objs.iter().zip(conflicts.iter()) {
+      if old_objs.len() == 0 {
+        out_objs.push(svt_obj.clone());
+        applied_count += 1;
+        continue;
+      }
+
+      if merge_policy == MergePolicy::KeepExisting {
+        out_objs.extend(old_objs.iter().map(|obj| (*obj).clone()));
+        let kept: Vec<&str> = old_objs.iter().map(|obj| obj.data.as_str()).collect();
+        self.merge_report.push(format!("{} kept {}, skipped {}", format_timestamp(svt_obj.time), kept.join(" "), svt_obj.data));
+        continue;
+      }
+
+      //the closest existing line (earliest on ties) decides what gets carried over
+      let old_obj = *old_objs.iter().min_by_key(|obj| i32::abs(obj.time - svt_obj.time)).unwrap();
+      let mut merged = svt_obj.clone();
+      match merge_policy {
+        MergePolicy::Multiply => merged.beatlength = -svt_obj.beatlength * old_obj.beatlength / 100.0,
+        MergePolicy::KeepSounds => {
+          merged.sampleset = old_obj.sampleset;
+          merged.sampleindex = old_obj.sampleindex;
+          merged.volume = old_obj.volume;
+        },
+        MergePolicy::KeepEffects => merged.effects = old_obj.effects,
+        _ => {},
+      }
+      merged.data = format_timing_point(&merged, self.precision);
+
+      let replaced: Vec<&str> = old_objs.iter().map(|obj| obj.data.as_str()).collect();
+      self.merge_report.push(format!("{} {:?} {} with {} -> {}", format_timestamp(svt_obj.time), merge_policy, replaced.join(" "), svt_obj.data, merged.data));
+      out_objs.push(merged);
+      dropped_objs.extend(old_objs.iter());
+      applied_count += 1;
+    }
+
     //uninherited ^ 1 indicates priority, while (time, uninherited) should be unique
-    out_objs.sort_by_key(|k| (k.time, k.uninherited ^ 1));
-    out_objs.dedup_by_key(|k| (k.time, k.uninherited));
+    //kept existing lines can't collide with tool points here, those were skipped above
+    out_objs.sort_by(cmp_lines);
+    out_objs.dedup_by(|a, b| a.exact_time() == b.exact_time() && a.uninherited == b.uninherited);
+
+    //dropped lines can carry hitsound or kiai changes, put those back wherever the merged lines no longer match them
+    //the restored line takes the sv (and new volume) of the merged line it falls under, so sv is not affected
+    dropped_objs.sort_by(|a, b| cmp_lines(a, b));
+    for old_obj in dropped_objs {
+      let idx = out_objs.partition_point(|obj| obj.exact_time() <= old_obj.exact_time());
+      if idx == 0 {
+        continue;
+      }
+
+      let out_line = &out_objs[idx - 1];
+      let same_volume = volume_changed || out_line.volume == old_obj.volume;
+      if out_line.sampleset == old_obj.sampleset && out_line.sampleindex == old_obj.sampleindex && out_line.effects == old_obj.effects && same_volume {
+        continue;
+      }
+
+      let mut restored = old_obj.clone();
+      restored.beatlength = if out_line.uninherited == 1 { -100.0 } else { out_line.beatlength };
+      if volume_changed {
+        restored.volume = out_line.volume;
+      }
+      restored.data = format_timing_point(&restored, self.precision);
+      self.merge_report.push(format!("{} restored sounds/effects of {} as {}", format_timestamp(restored.time), old_obj.data, restored.data));
+
+      //a merged inherited line at the same time would override the restored one, take its place instead
+      if out_line.time == restored.time && out_line.uninherited == 0 {
+        out_objs[idx - 1] = restored;
+      } else {
+        out_objs.insert(idx, restored);
+      }
+    }
+
+    for line in self.merge_report.iter() {
+      eprintln!("[merge] {}", line);
+    }
+
+    //drop lines that no longer change anything, from this pass or earlier ones
+    self.optimized_lines = 0;
+    if optimize {
+      let object_times: Vec<i32> = self.all_objs.iter().filter(|obj| obj.class == 3 || (obj.class >= 5 && obj.class <= 8)).map(|obj| obj.time).collect();
+      let (kept, removed) = optimize::optimize_timing_points(out_objs, &object_times);
+      out_objs = kept;
+      self.optimized_lines = removed.len();
+    }
+
+    //sliders change duration when the sv under them changes, either rewrite their lengths or just report them
+    let slider_rewrites = self.check_slider_lengths(&out_objs, fix_sliders);
+
+    Ok((out_objs, slider_rewrites, applied_count))
+  }
+
+  //timing points of the map with the clear ranges taken out, in chronological order
+  //a range clears from its start up to but not including its end, and whatever was in effect at the end is kept there
+  fn cleared_lines(&self) -> Vec<MapObject> {
+    let mut lines: Vec<MapObject> = self.all_objs.iter().filter(|obj| obj.class <= 1).cloned().collect();
+    let no_tool_lines = HashSet::new();
+    let tool_lines = self.tool_lines.as_ref().unwrap_or(&no_tool_lines);
+
+    for range in self.clear_ranges.iter() {
+      let clear = range.clear.unwrap();
+      let (start, end) = (range.start.time, range.end.time);
+      let before = lines.iter().take_while(|obj| obj.exact_time() < start as f64).last().cloned();
+      let at_end = line_at(lines.iter(), end as f64).cloned();
+
+      let count = lines.len();
+      lines.retain(|obj| obj.uninherited == 1 || obj.time < start || obj.time >= end || !(clear.all || tool_lines.contains(obj.data.trim())));
+      eprintln!("[clear] {}-{} removed {} lines", format_timestamp(start), format_timestamp(end), count - lines.len());
+
+      //the line that was in effect at the end may have been removed, carry it over to the end
+      if let Some(at_end) = at_end {
+        let still_there = lines.iter().any(|obj| obj.time == at_end.time && obj.uninherited == at_end.uninherited);
+        let now_at_end = line_at(lines.iter(), end as f64);
+        if !still_there && !now_at_end.map_or(false, |obj| same_state(obj, &at_end)) {
+          let mut kept = at_end.clone();
+          kept.time = end;
+          kept.time_fraction = 0.0;
+          kept.data = format_timing_point(&kept, self.precision);
+          eprintln!("[clear] {} kept {} as {}", format_timestamp(end), at_end.data, kept.data);
+          lines.push(kept);
+        }
+      }
+
+      //a line with the state from before the range, unless one of the map's own lines is already at the start
+      //without any line before the range, osu!'s defaults apply there: 1.0x with the map's sampleset and volume
+      let taken = lines.iter().any(|obj| obj.time == start && obj.uninherited == 0);
+      if clear.reset && !taken {
+        let mut reset = before.unwrap_or(MapObject{
+          beatlength: -100.0,
+          meter: 4,
+          sampleset: self.timing_defaults.sampleset,
+          volume: self.timing_defaults.volume,
+          ..Default::default()
+        });
+        if reset.uninherited == 1 {
+          reset.beatlength = -100.0;
+        }
+        reset.class = 1;
+        reset.uninherited = 0;
+        reset.time = start;
+        reset.time_fraction = 0.0;
+        reset.effects &= !8;
+        reset.data = format_timing_point(&reset, self.precision);
+        eprintln!("[clear] {} reset to {}", format_timestamp(start), reset.data);
+        lines.push(reset);
+      }
+
+      lines.sort_by(cmp_lines);
+    }
+
+    lines
+  }
+
+  //dry run, the timing point changes writing the current output points would make and the map as it would be written
+  pub fn diff_output_points(&mut self, min_spacing_text: String, merge_policy_text: String, fix_sliders: bool, opt: &ui::AppOptions) -> Result<(Vec<TimingDiff>, String)> {
+    let (out_objs, slider_rewrites, _) = self.merge_output_points(min_spacing_text, merge_policy_text, fix_sliders, opt.optimize, opt.vol)?;
+    let old_objs: Vec<MapObject> = self.all_objs.iter().filter(|obj| obj.class <= 1).cloned().collect();
+    let (out_string, _) = self.render_output(&out_objs, &slider_rewrites, &self.filename, false)?;
+
+    Ok((diff::diff_timing_points(&old_objs, &out_objs), out_string))
+  }
+
+  //write the current output points to the destination file, using the input file as a template for everything except timing points
+  pub fn write_output_points(&mut self, min_spacing_text: String, merge_policy_text: String, in_filename: String, out_filename: String, preview: bool, fix_sliders: bool, opt: &ui::AppOptions) -> Result<usize> {
+    let (out_objs, slider_rewrites, applied_count) = self.merge_output_points(min_spacing_text, merge_policy_text, fix_sliders, opt.optimize, opt.vol)?;
 
     //make backup before writing file, don't write without backing up
-    if let Err(e) = fs::copy(&in_filename, "backup.osu") {
-      return Err(anyhow!("[backup] error backing up file {}", e));
-    }
-
-    // read file line by line
-    let mut out_string = String::new();
-    let mut bool_timing = false;
-    if let Ok(lines) = read_lines(&in_filename) {
-      for line in lines {
-        if let Ok(s) = line {
-          // we want everything except timingpoints lines
-          match s.as_str() {
-            "[General]" | "[Editor]" | "[Metadata]" | "[Difficulty]" | "[Events]" | "[Colours]" | "[HitObjects]" => {
-              bool_timing = false;
-              out_string += &s;
-              out_string += "\n";
-            },
-            "[TimingPoints]" => {
-              bool_timing = true;
-              out_string += &s;
-              out_string += "\n";
-              for out_obj in out_objs.iter() {
-                out_string += &out_obj.data;
-                out_string += "\n";
-              }
-              out_string += "\n";
-            },
-            _ => {
-              let s_tokens: Vec<&str> = s.split(":").collect();
-              if preview && s_tokens.len() == 2 && s_tokens[0] == "Version" {
-                out_string += "Version:preview\n";
-              } else if !bool_timing {
-                out_string += &s;
-                out_string += "\n";
-              }
-            },
-          }
+    //previews are written beside the map and leave it untouched, backing those up would only push real backups out
+    if !preview {
+      let backup_path = backup::create_backup(&in_filename, opt)?;
+      eprintln!("[backup] backed up to {}", backup_path.display());
+    }
+
+    let (out_string, out_hits) = self.render_output(&out_objs, &slider_rewrites, &in_filename, preview)?;
+    let out_points: Vec<String> = out_objs.iter().map(|obj| obj.data.clone()).collect();
+    write_verified(&out_filename, &out_string, &out_points, &out_hits)?;
+    Ok(applied_count)
+  }
+
+  //the input file with its timing points replaced and sliders rewritten, and the hit object lines in it
+  fn render_output(&self, out_objs: &[MapObject], slider_rewrites: &HashMap<String, String>, in_filename: &str, preview: bool) -> Result<(String, Vec<String>)> {
+    let map_file = OsuFile::read(in_filename).context("[write] input file/filename invalid")?;
+
+    //timing point comments have nowhere to go between the new points, they're kept at the start of the section
+    let timing_comments: Vec<&str> = map_file.lines.iter().filter(|line| line.section == "TimingPoints" && line.is_comment()).map(|line| line.text.as_str()).collect();
+
+    // we want everything except timingpoints lines, every other line is written as it was
+    let mut out_lines: Vec<String> = Vec::new();
+    let mut out_hits = Vec::new();
+    for line in map_file.lines.iter() {
+      if line.is_header() {
+        out_lines.push(line.text.clone());
+        if line.section == "TimingPoints" {
+          out_lines.extend(timing_comments.iter().map(|comment| comment.to_string()));
+          out_lines.extend(out_objs.iter().map(|out_obj| out_obj.data.clone()));
+          out_lines.push(String::new());
         }
+        continue;
+      }
+
+      if line.section == "TimingPoints" {
+        continue;
+      }
+
+      match (line.section.as_str(), line.key_value()) {
+        ("Metadata", Some(("Version", version))) if preview => {
+          out_lines.push(format!("Version:{}", preview::preview_version(version)));
+          continue;
+        },
+        //a preview with the original's id would be taken for the original difficulty
+        ("Metadata", Some(("BeatmapID", _))) if preview => {
+          out_lines.push(String::from("BeatmapID:0"));
+          continue;
+        },
+        _ => {},
+      }
+
+      match line.content() {
+        Some(s) if line.section == "HitObjects" => {
+          let out_hit = slider_rewrites.get(s).cloned().unwrap_or(line.text.clone());
+          out_lines.push(out_hit.clone());
+          out_hits.push(out_hit);
+        },
+        _ => out_lines.push(line.text.clone()),
       }
-    } else {
-      return Err(anyhow!("[write] input file/filename invalid"));
     }
 
-    let mut out_file = File::create(out_filename).unwrap();
-    let _ = write!(&mut out_file, "{}", out_string);
-    Ok(svt_objs.len())
+    Ok((map_file.join(&out_lines), out_hits))
+  }
+
+  //what the last merge did beyond placing points: lines merged with existing ones and sliders whose sv changed
+  pub fn apply_report(&self, fix_sliders: bool) -> Vec<String> {
+    let mut report = Vec::new();
+    if self.merge_report.len() > 0 {
+      report.push(format!("{} merged with existing inh. lines:", self.merge_report.len()));
+      report.extend(self.merge_report.iter().map(|line| format!("  {}", line)));
+    }
+    if self.changed_sliders.len() > 0 {
+      let slider_times: Vec<String> = self.changed_sliders.iter().map(|t| format_timestamp(*t)).collect();
+      let action = if fix_sliders { "rewritten to keep their duration" } else { "changed duration" };
+      report.push(format!("{} sliders {}:", self.changed_sliders.len(), action));
+      report.push(format!("  {}", slider_times.join(", ")));
+    }
+    report
+  }
+
+  //find sliders whose sv changes between the current and new timing points and collect them in changed_sliders
+  //if fix_sliders is set, returns rewritten hit object lines with lengths scaled so that slider end times don't move
+  fn check_slider_lengths(&mut self, out_objs: &Vec<MapObject>, fix_sliders: bool) -> HashMap<String, String> {
+    let mut rewrites = HashMap::new();
+    self.changed_sliders.clear();
+
+    for obj in self.all_objs.iter() {
+      if obj.class != 3 || obj.hit_type & 2 == 0 {
+        continue;
+      }
+
+      let old_sv = slider_sv_at(self.all_objs.iter().filter(|o| o.class <= 1), obj.time);
+      let new_sv = slider_sv_at(out_objs.iter(), obj.time);
+      if (new_sv - old_sv).abs() < 1e-4 {
+        continue;
+      }
+
+      self.changed_sliders.push(obj.time);
+
+      //length is the 8th field, duration stays the same when length scales with sv
+      let mut tokens: Vec<String> = obj.data.split(",").map(|t| t.to_string()).collect();
+      let new_length = obj.length * new_sv / old_sv;
+      tokens[7] = format!("{}", (new_length * 10000.0).round() / 10000.0);
+      eprintln!("[slider] {} sv {} -> {}, length {} -> {}", format_timestamp(obj.time), old_sv, new_sv, obj.length, tokens[7]);
+
+      if fix_sliders {
+        rewrites.insert(obj.data.clone(), tokens.join(","));
+      }
+    }
+
+    rewrites
   }
 
   pub fn print_debug(&self) {
-    println!("\n[svt] DEBUG all_objs:");
+    eprintln!("\n[svt] DEBUG all_objs:");
 
     let mut uni_count = 0;
     let mut inh_count = 0;
     let mut snp_count = 0;
     let mut hit_count = 0;
+    let mut slider_count = 0;
+    let mut bar_count = 0;
 
     for map_obj in self.all_objs.iter() {
       match map_obj.class {
         0 => {
-          println!("[svt] uni {}", map_obj.data.trim());
+          eprintln!("[svt] uni {}", map_obj.data.trim());
           uni_count += 1;
         },
         1 => {
-          println!("[svt] inh {}", map_obj.data.trim());
+          eprintln!("[svt] inh {}", map_obj.data.trim());
           inh_count += 1;
         },
         2 => {
-          println!("[svt] snp {}", map_obj.time);
+          eprintln!("[svt] snp {}", map_obj.time);
           snp_count += 1;
         },
         3 => {
-          //println!("[svt] hit {}", map_obj.time);
+          //eprintln!("[svt] hit {}", map_obj.time);
           hit_count += 1;
         },
+        5..=8 => {
+          slider_count += 1;
+        },
+        9 => {
+          bar_count += 1;
+        },
         _ => {
-          println!("[svt] ???");
+          eprintln!("[svt] ???");
         },
       }
     }
-    println!("[svt] counts:\nuni: {}\ninh: {}\nsnp: {}\nhit: {}\n", uni_count, inh_count, snp_count, hit_count);
+    eprintln!("[svt] counts:\nuni: {}\ninh: {}\nsnp: {}\nhit: {}\nslider/spinner parts: {}\nbar: {}\n", uni_count, inh_count, snp_count, hit_count, slider_count, bar_count);
   }
 }
 
 //creates a MapObject from timing point/hit point strings (in .osu file format)
+//timing points missing fields from older format versions use osu!'s defaults
 //only produces MapObjects of classes 0 (uni), 1 (inh), 3 (hit)
-fn create_map_object(p: String, timingpoint: bool) -> Result<MapObject> {
+pub fn create_map_object(p: String, timingpoint: bool) -> Result<MapObject> {
   let p_tokens: Vec<&str> = p.split(",").collect();
 
   let map_obj = if timingpoint {
-    //timing point
-    if p_tokens.len() != 8 {
-      return Err(anyhow!("[create] timing: incorrect format {}", p));
-    }
-  
-    let time_raw = p_tokens[0].parse::<f32>()?;
-    let time = time_raw.round() as i32;
-    let beatlength = p_tokens[1].parse::<f32>()?;
-    let meter = p_tokens[2].parse::<i32>()?;
-    let sampleset = p_tokens[3].parse::<i32>()?;
-    let sampleindex = p_tokens[4].parse::<i32>()?;
-    let volume = p_tokens[5].parse::<i32>()?;
-    let uninherited = p_tokens[6].parse::<i32>()?;
-    let effects = p_tokens[7].parse::<i32>()?;
-
-    MapObject{class: uninherited ^ 1, time: time, beatlength: beatlength, meter: meter, sampleset: sampleset, sampleindex: sampleindex, volume: volume, uninherited: uninherited, effects: effects, data: p}
+    create_timing_point(p, &TimingDefaults::default())?
   } else {
     //hit point
-    if p_tokens.len() < 2 {
+    if p_tokens.len() < 5 {
       return Err(anyhow!("[create] hit: incorrect format {}", p));
     }
 
-    let time_raw = p_tokens[2].parse::<f32>()?;
+    let x = p_tokens[0].parse::<f64>()?.round() as i32;
+    let time_raw = p_tokens[2].parse::<f64>()?;
     let time = time_raw.round() as i32;
+    let hit_type = p_tokens[3].parse::<i32>()?;
+    let hitsound = p_tokens[4].parse::<i32>()?;
+
+    //slider end times need timing points and are filled in after loading
+    let mut end_time = time;
+    let mut slides = 0;
+    let mut length = 0.0;
+    if hit_type & 2 != 0 {
+      if p_tokens.len() < 8 {
+        return Err(anyhow!("[create] hit: incorrect slider format {}", p));
+      }
+      slides = p_tokens[6].parse::<i32>()?;
+      length = p_tokens[7].parse::<f64>()?;
+    } else if hit_type & 8 != 0 || hit_type & 128 != 0 {
+      //spinners end at token 5, holds at the first field of token 5
+      if p_tokens.len() < 6 {
+        return Err(anyhow!("[create] hit: incorrect spinner/hold format {}", p));
+      }
+      end_time = p_tokens[5].split(":").nth(0).unwrap_or("").parse::<f64>()?.round() as i32;
+    }
 
-    MapObject{class: 3, time: time, data: String::from(""), ..Default::default()}
+    //hit sample is normalSet:additionSet:index:volume:filename, holds prefix it with their end time
+    let hit_sample = if hit_type & 128 != 0 {
+      p_tokens[5].splitn(2, ":").nth(1).unwrap_or("")
+    } else if hit_type & 2 != 0 {
+      p_tokens.get(10).copied().unwrap_or("")
+    } else if hit_type & 8 != 0 {
+      p_tokens.get(6).copied().unwrap_or("")
+    } else {
+      p_tokens.get(5).copied().unwrap_or("")
+    };
+    let sample_tokens: Vec<&str> = hit_sample.split(":").collect();
+    let sampleset = sample_tokens[0].parse::<i32>().unwrap_or(0);
+    let sampleindex = sample_tokens.get(2).and_then(|i| i.parse::<i32>().ok()).unwrap_or(0);
+
+    MapObject{class: 3, time: time, sampleset: sampleset, sampleindex: sampleindex, hit_type: hit_type, hitsound: hitsound, x: x, end_time: end_time, slides: slides, length: length, data: p, ..Default::default()}
   };
 
   return Ok(map_obj);
 }
 
-//convenience function for reading file line by line
-fn read_lines<P>(full_path: P) -> io::Result<io::Lines<io::BufReader<File>>> where P: AsRef<Path>, {
-  let file = File::open(full_path)?;
-  Ok(io::BufReader::new(file).lines())
-}
\ No newline at end of file
+//write a map to a temporary file beside the destination, flush it to disk and read it back before replacing the destination
+//the destination is left untouched unless the written timing points and hit objects match the intended ones
+fn write_verified(out_filename: &str, out_string: &str, timing_points: &[String], hit_objects: &[String]) -> Result<()> {
+  let tmp_filename = format!("{}.svt-tmp", out_filename);
+
+  let write_result = write_synced(&tmp_filename, out_string).and_then(|_| verify_written(&tmp_filename, timing_points, hit_objects));
+  if let Err(e) = write_result {
+    let _ = fs::remove_file(&tmp_filename);
+    return Err(e);
+  }
+
+  fs::rename(&tmp_filename, out_filename).with_context(|| format!("[write] couldn't replace {}", out_filename))
+}
+
+//write a whole map as given, such as a restored backup, through the same temp file and verification as output maps
+pub fn write_map(out_filename: &str, contents: &str) -> Result<()> {
+  let map_file = OsuFile::parse(contents);
+  let section_lines = |section: &str| -> Vec<String> {
+    map_file.lines.iter().filter(|line| line.section == section && line.content().is_some()).map(|line| line.text.clone()).collect()
+  };
+  write_verified(out_filename, contents, &section_lines("TimingPoints"), &section_lines("HitObjects"))
+}
+
+pub fn write_synced(filename: &str, out_string: &str) -> Result<()> {
+  let mut out_file = File::create(filename).with_context(|| format!("[write] couldn't create {}", filename))?;
+  out_file.write_all(out_string.as_bytes()).context("[write] couldn't write output")?;
+  out_file.sync_all().context("[write] couldn't flush output to disk")
+}
+
+//creates a timing point MapObject, only time and beatlength are required
+//missing fields are filled the way osu! reads older maps: meter 4, the map's sampleset and volume, sample index 0, no effects
+pub fn create_timing_point(p: String, defaults: &TimingDefaults) -> Result<MapObject> {
+  let p_tokens: Vec<&str> = p.split(",").map(|t| t.trim()).collect();
+  if p_tokens.len() < 2 || p_tokens.len() > 8 {
+    return Err(anyhow!("[create] timing: incorrect format {}", p));
+  }
+
+  let field = |i: usize, default: i32| -> Result<i32> {
+    match p_tokens.get(i) {
+      Some(token) => Ok(token.parse::<i32>()?),
+      None => Ok(default),
+    }
+  };
+
+  let time_raw = p_tokens[0].parse::<f64>()?;
+  let time = time_raw.floor() as i32;
+  let time_fraction = time_raw - time as f64;
+  let beatlength = p_tokens[1].parse::<f64>()?;
+  //osu! reads a meter of 0 as 4/4
+  let meter = match field(2, 4)? {
+    0 => 4,
+    meter => meter,
+  };
+  let sampleset = field(3, defaults.sampleset)?;
+  let sampleindex = field(4, 0)?;
+  let volume = field(5, defaults.volume)?;
+  //lines without the uninherited field are red lines, unless their negative beatlength makes them an sv change
+  let uninherited = field(6, if beatlength < 0.0 { 0 } else { 1 })?;
+  let effects = field(7, 0)?;
+
+  Ok(MapObject{class: uninherited ^ 1, time: time, time_fraction: time_fraction, beatlength: beatlength, meter: meter, sampleset: sampleset, sampleindex: sampleindex, volume: volume, uninherited: uninherited, effects: effects, data: p, ..Default::default()})
+}
+
+//re-parse a written map and compare its timing points and hit objects with the intended lines
+fn verify_written(filename: &str, timing_points: &[String], hit_objects: &[String]) -> Result<()> {
+  let written_file = OsuFile::read(filename).context("[verify] couldn't read back output")?;
+  let mut written_points = Vec::new();
+  let mut written_hits = Vec::new();
+
+  for line in written_file.lines.iter() {
+    if line.content().is_none() {
+      continue;
+    } else if line.section == "TimingPoints" {
+      written_points.push(line.text.clone());
+    } else if line.section == "HitObjects" {
+      written_hits.push(line.text.clone());
+    }
+  }
+
+  compare_written("timing point", timing_points.iter().collect(), written_points.iter().collect(), true)?;
+  compare_written("hit object", hit_objects.iter().collect(), written_hits.iter().collect(), false)
+}
+
+fn compare_written(kind: &str, expected: Vec<&String>, written: Vec<&String>, timingpoint: bool) -> Result<()> {
+  if expected.len() != written.len() {
+    return Err(anyhow!("[verify] wrote {} {}s instead of {}", written.len(), kind, expected.len()));
+  }
+
+  for (expected_line, written_line) in expected.iter().zip(written.iter()) {
+    //lines must match and still parse, unless the line was unparseable to begin with
+    let parses = create_map_object(written_line.to_string(), timingpoint).is_ok() || create_map_object(expected_line.to_string(), timingpoint).is_err();
+    if expected_line != written_line || !parses {
+      return Err(anyhow!("[verify] {} {} was written as {}", kind, expected_line, written_line));
+    }
+  }
+
+  Ok(())
+}
+
+//timing point line for a map object, in .osu file format, with the beatlength rounded to precision significant digits
+fn format_timing_point(obj: &MapObject, precision: usize) -> String {
+  //decimal times are written back as they were read, f64 keeps them exactly
+  let time = if obj.time_fraction == 0.0 { obj.time.to_string() } else { obj.exact_time().to_string() };
+  format!("{},{},{},{},{},{},{},{}", time, format_decimal(obj.beatlength, precision), obj.meter, obj.sampleset, obj.sampleindex, obj.volume, obj.uninherited, obj.effects)
+}
+
+//a number rounded to a number of significant digits without trailing zeros or exponent, e.g. -76.9230769230769 for 15 digits
+//the osu! editor writes beatlengths with 15, so rounding there removes float noise like -75.00000000000003
+pub fn format_decimal(x: f64, digits: usize) -> String {
+  //zero has no magnitude, -0 is written as 0
+  if x == 0.0 {
+    return String::from("0");
+  }
+  if !x.is_finite() {
+    return format!("{}", x);
+  }
+
+  let magnitude = x.abs().log10().floor() as i32;
+  let decimals = digits as i32 - 1 - magnitude;
+  let mut out_string = if decimals > 0 {
+    format!("{:.*}", decimals as usize, x)
+  } else {
+    let scale = 10f64.powi(-decimals);
+    format!("{:.0}", (x / scale).round() * scale)
+  };
+
+  if out_string.contains(".") {
+    out_string = out_string.trim_end_matches("0").trim_end_matches(".").to_string();
+  }
+  if out_string == "-0" {
+    out_string = String::from("0");
+  }
+  out_string
+}
+
+//time in ms of a point numer/denom beats after a red line, computed from the red line rather than accumulated so it doesn't drift
+//the editor truncates snapped times to whole ms, allowing for floating point error just below a whole ms
+fn beat_time(red_time: f64, beatlength: f64, numer: f64, denom: f64) -> i32 {
+  (red_time + beatlength * numer / denom + 1e-6).floor() as i32
+}
+
+//whether two timing points leave sv, sounds and kiai the same, red lines counting as 1.0x
+fn same_state(a: &MapObject, b: &MapObject) -> bool {
+  let a_beatlength = if a.uninherited == 1 { -100.0 } else { a.beatlength };
+  let b_beatlength = if b.uninherited == 1 { -100.0 } else { b.beatlength };
+  a_beatlength == b_beatlength && a.sampleset == b.sampleset && a.sampleindex == b.sampleindex && a.volume == b.volume && a.effects & 1 == b.effects & 1
+}
+
+//chronological order of timing points by their exact times, red lines before green lines at the same time
+fn cmp_lines(a: &MapObject, b: &MapObject) -> cmp::Ordering {
+  a.exact_time().partial_cmp(&b.exact_time()).unwrap_or(cmp::Ordering::Equal).then((a.uninherited ^ 1).cmp(&(b.uninherited ^ 1)))
+}
+
+//last timing point at or before time t, given timing points in chronological order
+fn line_at<'a, I>(timing_points: I, t: f64) -> Option<&'a MapObject> where I: Iterator<Item = &'a MapObject>, {
+  timing_points.take_while(|point| point.exact_time() <= t).last()
+}
+
+//sv used for slider velocity at time t, given timing points in chronological order
+fn slider_sv_at<'a, I>(timing_points: I, t: i32) -> f64 where I: Iterator<Item = &'a MapObject>, {
+  let mut sv = 1.0;
+  for point in timing_points {
+    if point.exact_time() > t as f64 {
+      break;
+    }
+    sv = if point.uninherited == 1 { 1.0 } else { -100.0 / point.beatlength };
+  }
+
+  //osu! clamps sv to [0.1, 10] for slider velocity
+  f64::max(0.1, f64::min(10.0, sv))
+}
+
+//format a time in ms as an editor timestamp (mm:ss:ms)
+pub fn format_timestamp(t: i32) -> String {
+  let sign = if t < 0 { "-" } else { "" };
+  let t = t.abs();
+  format!("{}{:02}:{:02}:{:03}", sign, t / 60000, (t / 1000) % 60, t % 1000)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  //180 bpm as the editor writes it, 60000 / 180 rounded to 15 significant digits
+  const BEATLENGTH_180: f64 = 333.333333333333;
+
+  //a map with one red line and a hit object ending it at 3000
+  fn map_with_red_line(time: i32, time_fraction: f64, effects: i32) -> SVT {
+    let mut svt = SVT::default();
+    svt.all_objs.push(MapObject{class: 0, time: time, time_fraction: time_fraction, beatlength: BEATLENGTH_180, meter: 4, uninherited: 1, effects: effects, ..Default::default()});
+    svt.all_objs.push(MapObject{class: 3, time: 3000, ..Default::default()});
+    svt
+  }
+
+  fn times(objs: &[MapObject]) -> Vec<i32> {
+    objs.iter().map(|obj| obj.time).collect()
+  }
+
+  #[test]
+  fn format_decimal_rounds_to_significant_digits() {
+    assert_eq!(format_decimal(-76.9230769230769, 15), "-76.9230769230769");
+    assert_eq!(format_decimal(-75.00000000000003, 15), "-75");
+    assert_eq!(format_decimal(-0.0, 15), "0");
+    assert_eq!(format_decimal(9.9999999999999999, 15), "10");
+    //rounding up can carry into a new digit
+    assert_eq!(format_decimal(99.99999999999999, 15), "100");
+    assert_eq!(format_decimal(BEATLENGTH_180, 15), "333.333333333333");
+  }
+
+  #[test]
+  fn beat_time_matches_editor_ticks() {
+    //whole beats land on the ms the editor shows, 999.999999999999 is 1000 rather than 999
+    let beats: Vec<i32> = (0..6).map(|k| beat_time(0.0, BEATLENGTH_180, k as f64, 1.0)).collect();
+    assert_eq!(beats, vec![0, 333, 666, 1000, 1333, 1666]);
+    let quarters: Vec<i32> = (0..9).map(|k| beat_time(0.0, BEATLENGTH_180, k as f64, 4.0)).collect();
+    assert_eq!(quarters, vec![0, 83, 166, 250, 333, 416, 500, 583, 666]);
+
+    //a beatlength cut short really is short, 3 beats of 333.333 end before 1000
+    let beats: Vec<i32> = (0..6).map(|k| beat_time(0.0, 333.333, k as f64, 1.0)).collect();
+    assert_eq!(beats, vec![0, 333, 666, 999, 1333, 1666]);
+  }
+
+  #[test]
+  fn beat_time_from_decimal_red_line() {
+    let beats: Vec<i32> = (0..7).map(|k| beat_time(1000.5, BEATLENGTH_180, k as f64, 1.0)).collect();
+    assert_eq!(beats, vec![1000, 1333, 1667, 2000, 2333, 2667, 3000]);
+  }
+
+  #[test]
+  fn snappings_from_decimal_red_line() {
+    let svt = map_with_red_line(1000, 0.5, 0);
+    assert_eq!(times(&svt.generate_snappings(&[(1.0, 1.0)])), vec![1000, 1333, 1667, 2000, 2333, 2667, 3000]);
+
+    //1/3 shares every beat with 1/1, those are only added once
+    let snappings = times(&svt.generate_snappings(&[(1.0, 1.0), (1.0, 3.0)]));
+    assert_eq!(&snappings[..7], &[1000, 1111, 1222, 1333, 1444, 1556, 1667]);
+    assert_eq!(snappings.len(), 19);
+  }
+
+  fn load_fixture(name: &str, opt: &ui::AppOptions) -> SVT {
+    let mut svt = SVT::default();
+    let filename = format!("{}/tests/fixtures/{}", env!("CARGO_MANIFEST_DIR"), name);
+    svt.load_osu(&filename, opt).unwrap();
+    svt
+  }
+
+  //timing.osu is a map saved by the editor with a red line at a decimal time, then 3/4 and 7/4 sections, the last omitting its first barline
+  //expected times are the ticks and measure lines the editor timeline shows for it
+
+  #[test]
+  fn fixture_snappings() {
+    let svt = load_fixture("timing.osu", &ui::AppOptions::default());
+    let snappings: Vec<i32> = svt.all_objs.iter().filter(|obj| obj.class == 2).map(|obj| obj.time).collect();
+    assert_eq!(snappings, vec![1000, 1333, 1667, 2000, 2333, 2667, 3000, 3333, 3667, 4167, 4667, 5167, 5667, 6167, 6667, 7095, 7524, 7952, 8381, 8809, 9238, 9667]);
+
+    let halves = times(&svt.generate_snappings(&[(1.0, 2.0)]));
+    assert_eq!(&halves[..4], &[1000, 1167, 1333, 1500]);
+    assert_eq!(&halves[16..19], &[3667, 3917, 4167]);
+    assert_eq!(&halves[28..31], &[6667, 6881, 7095]);
+    assert_eq!(halves.len(), 44);
+  }
+
+  #[test]
+  fn fixture_barlines() {
+    let svt = load_fixture("timing.osu", &ui::AppOptions::default());
+    let barlines: Vec<i32> = svt.all_objs.iter().filter(|obj| obj.class == 9).map(|obj| obj.time).collect();
+    assert_eq!(barlines, vec![1000, 2333, 3667, 5167, 9667]);
+  }
+
+  //v5.osu has timing points with 2, 6 and 7 fields, and a meter of 0
+  #[test]
+  fn fills_short_timing_points() {
+    let svt = load_fixture("v5.osu", &ui::AppOptions::default());
+    let lines: Vec<&MapObject> = svt.all_objs.iter().filter(|obj| obj.class <= 1).collect();
+    let fields: Vec<(f64, i32, i32, i32, i32, i32, i32)> = lines.iter().map(|obj| (obj.beatlength, obj.meter, obj.sampleset, obj.sampleindex, obj.volume, obj.uninherited, obj.effects)).collect();
+    assert_eq!(fields, vec![
+      (500.0, 4, 2, 0, 70, 1, 0),
+      (-50.0, 4, 1, 0, 80, 0, 0),
+      (400.0, 3, 2, 1, 60, 1, 0),
+      (-100.0, 4, 2, 0, 60, 0, 0),
+    ]);
+    //lines are written back as they were unless upgrading
+    assert_eq!(lines[0].data, "1000,500");
+
+    let opt = ui::AppOptions{upgrade_timing_points: true, ..Default::default()};
+    let svt = load_fixture("v5.osu", &opt);
+    let upgraded: Vec<&str> = svt.all_objs.iter().filter(|obj| obj.class <= 1).map(|obj| obj.data.as_str()).collect();
+    assert_eq!(upgraded, vec!["1000,500,4,2,0,70,1,0", "3000,-50,4,1,0,80,0,0", "5000,400,3,2,1,60,1,0", "6000,-100,4,2,0,60,0,0"]);
+    assert_eq!(svt.skipped_lines, 0);
+  }
+
+  #[test]
+  fn barlines_from_decimal_red_line() {
+    let svt = map_with_red_line(1000, 0.5, 0);
+    assert_eq!(times(&svt.generate_barlines()), vec![1000, 2333]);
+
+    //omit first barline only skips the one on the red line
+    let svt = map_with_red_line(1000, 0.5, 8);
+    assert_eq!(times(&svt.generate_barlines()), vec![2333]);
+  }
+}
@@ -14,13 +14,14 @@ const WINDOW_LAYOUT_PADDING: Rect<D> = Rect{ start: PT_0, end: PT_10, top: PT_0,
 const STARTUP_WINDOW_X: i32 = -100;
 const STARTUP_WINDOW_Y: i32 = -100;
 const DEFAULT_WINDOW_WIDTH: u32 = 300;
-const DEFAULT_WINDOW_HEIGHT: u32 = 350;
+const DEFAULT_WINDOW_HEIGHT: u32 = 450;
 const WINDOW_TITLE: &str = "SVT";
 const SVT_OPTIONS_FILE: &str = "svt_config.txt";
+//names of svt::MergePolicy values, as shown in the ui and saved in the config
+const MERGE_POLICIES: [&str; 5] = ["replace", "keep existing", "multiply", "keep sounds", "keep effects"];
 
 use anyhow::{anyhow, Result};
 use serde::{Serialize, Deserialize};
-use libxch;
 
 use std::{cell::RefCell};
 use std::cmp;
@@ -30,10 +31,15 @@ use std::fs::{self, File};
 use std::io::Write;
 use std::path::Path;
 
+use crate::fingerprint::MapFingerprint;
+use crate::history::History;
+use crate::input::InputRange;
+use crate::preview;
 use crate::svt;
 
 //TODO consider using this as a general config parameter to pass around in functions involving SVT
 #[derive(Serialize, Deserialize, Debug)]
+#[serde(default)]
 pub struct AppOptions {
   pub map: String,
   pub inh_times: String,
@@ -46,6 +52,18 @@ pub struct AppOptions {
   pub hits: bool,
   pub snappings: bool,
   pub inh_lines: bool,
+  pub slider_repeats: bool,
+  pub slider_tails: bool,
+  pub slider_ticks: bool,
+  pub spinner_ends: bool,
+  pub barlines: bool,
+  pub hit_filter: String,
+  pub merge_policy: String,
+  pub backup_folder: String,
+  pub backup_count: u32,
+  pub backup_days: u32,
+  pub precision: u32,
+  pub upgrade_timing_points: bool,
   pub offset: String,
   pub buffer: String,
   pub min_spacing: String,
@@ -53,9 +71,11 @@ pub struct AppOptions {
   pub flat_scaling: bool,
   pub flat_change: String,
   pub flat_scaling_change: String,
-  pub snapping_numer: String,
-  pub snapping_denom: String,
+  pub snapping: String,
+  pub snapping_end: String,
   pub ignore_bpm: bool,
+  pub fix_sliders: bool,
+  pub optimize: bool,
   pub pos_x: i32,
   pub pos_y: i32,
   pub width: u32,
@@ -77,6 +97,18 @@ impl Default for AppOptions {
       hits: true,
       snappings: false,
       inh_lines: false,
+      slider_repeats: false,
+      slider_tails: false,
+      slider_ticks: false,
+      spinner_ends: false,
+      barlines: false,
+      hit_filter: String::from(""),
+      merge_policy: String::from(MERGE_POLICIES[0]),
+      backup_folder: String::from(""),
+      backup_count: 20,
+      backup_days: 30,
+      precision: 15,
+      upgrade_timing_points: false,
       offset: String::from("-1"),
       buffer: String::from("3"),
       min_spacing: String::from("3"),
@@ -84,9 +116,11 @@ impl Default for AppOptions {
       flat_scaling: false,
       flat_change: String::from("0.0"),
       flat_scaling_change: String::from("1.0"),
-      snapping_numer: String::from("1"),
-      snapping_denom: String::from("1"),
+      snapping: String::from("1/1"),
+      snapping_end: String::from(""),
       ignore_bpm: false,
+      fix_sliders: false,
+      optimize: false,
       pos_x: cmp::max(0, nwg::Monitor::width() / 2 - (DEFAULT_WINDOW_WIDTH / 2) as i32),
       pos_y: cmp::max(0, nwg::Monitor::height() / 2 - (DEFAULT_WINDOW_HEIGHT / 2) as i32),
       width: DEFAULT_WINDOW_WIDTH,
@@ -97,6 +131,31 @@ impl Default for AppOptions {
   }
 }
 
+//saved options, or the defaults if there are none yet
+pub fn read_options() -> AppOptions {
+  let app_options_string = fs::read_to_string(SVT_OPTIONS_FILE).unwrap_or(String::from(""));
+  match serde_json::from_str::<serde_json::Value>(&app_options_string) {
+    Ok(mut app_options_value) => {
+      migrate_options(&mut app_options_value);
+      serde_json::from_value(app_options_value).unwrap_or(AppOptions{..Default::default()})
+    },
+    Err(_) => AppOptions{..Default::default()},
+  }
+}
+
+//configs saved before multiple snapping divisors kept a single divisor as snapping_numer/snapping_denom
+fn migrate_options(app_options_value: &mut serde_json::Value) {
+  if let Some(options) = app_options_value.as_object_mut() {
+    if options.contains_key("snapping") || !(options.contains_key("snapping_numer") || options.contains_key("snapping_denom")) {
+      return;
+    }
+
+    let numer = options.get("snapping_numer").and_then(|v| v.as_str()).unwrap_or("1").trim().to_string();
+    let denom = options.get("snapping_denom").and_then(|v| v.as_str()).unwrap_or("1").trim().to_string();
+    options.insert(String::from("snapping"), serde_json::Value::String(format!("{}/{}", numer, denom)));
+  }
+}
+
 #[derive(Default, NwgUi)]
 pub struct UI {
   //start window as 0-sized and off-screen, then move on-screen after config is loaded to prevent flashing
@@ -117,10 +176,22 @@ pub struct UI {
 
   #[nwg_control(flags: "VISIBLE")]
   #[nwg_layout_item(layout: window_layout, margin: MARGIN,
-    size: Size { width: D::Percent(1.0), height: D::Points(140.0) },
+    size: Size { width: D::Percent(1.0), height: D::Points(180.0) },
   )]
   pub options_frame: nwg::Frame,
 
+  #[nwg_control(flags: "VISIBLE")]
+  #[nwg_layout_item(layout: window_layout, margin: MARGIN,
+    size: Size { width: D::Percent(1.0), height: D::Points(25.0) },
+  )]
+  pub filter_frame: nwg::Frame,
+
+  #[nwg_control(flags: "VISIBLE")]
+  #[nwg_layout_item(layout: window_layout, margin: MARGIN,
+    size: Size { width: D::Percent(1.0), height: D::Points(25.0) },
+  )]
+  pub merge_frame: nwg::Frame,
+
   #[nwg_control(flags: "VISIBLE")]
   #[nwg_layout_item(layout: window_layout, margin: MARGIN,
     size: Size { width: D::Percent(1.0), height: D::Points(55.0) },
@@ -134,7 +205,7 @@ pub struct UI {
   pub applyundo_frame: nwg::Frame,
 
   //outline around the apply controls
-  #[nwg_control(size: (60, 140), position: (0, 0), parent: options_frame)]
+  #[nwg_control(size: (60, 180), position: (0, 0), parent: options_frame)]
   pub apply_frame: nwg::Frame,
 
   #[nwg_control(text: "Apply:", size: (45, 20), position: (2, 0), parent: apply_frame)]
@@ -171,7 +242,7 @@ pub struct UI {
   pub vol_check: nwg::CheckBox,
 
   //outline around the apply to controls
-  #[nwg_control(size: (70, 140), position: (59, 0), parent: options_frame)]
+  #[nwg_control(size: (70, 180), position: (59, 0), parent: options_frame)]
   pub apply_to_frame: nwg::Frame,
 
   #[nwg_control(text: "To:", size: (65, 20), position: (2, 0), parent: apply_to_frame)]
@@ -192,8 +263,33 @@ pub struct UI {
   #[nwg_events(OnButtonClick: [UI::update_config(SELF)])]
   pub inh_check: nwg::CheckBox,
 
+  //toggles slider repeat changes
+  #[nwg_control(text: "Repeats", size: (95, 20), position: (2, 80), check_state: Unchecked, parent: apply_to_frame)]
+  #[nwg_events(OnButtonClick: [UI::update_config(SELF)])]
+  pub repeat_check: nwg::CheckBox,
+
+  //toggles slider tail changes
+  #[nwg_control(text: "Tails", size: (95, 20), position: (2, 100), check_state: Unchecked, parent: apply_to_frame)]
+  #[nwg_events(OnButtonClick: [UI::update_config(SELF)])]
+  pub tail_check: nwg::CheckBox,
+
+  //toggles slider tick changes
+  #[nwg_control(text: "Ticks", size: (95, 20), position: (2, 120), check_state: Unchecked, parent: apply_to_frame)]
+  #[nwg_events(OnButtonClick: [UI::update_config(SELF)])]
+  pub tick_check: nwg::CheckBox,
+
+  //toggles spinner/hold end changes
+  #[nwg_control(text: "Spin. ends", size: (95, 20), position: (2, 140), check_state: Unchecked, parent: apply_to_frame)]
+  #[nwg_events(OnButtonClick: [UI::update_config(SELF)])]
+  pub spinner_end_check: nwg::CheckBox,
+
+  //toggles barline changes
+  #[nwg_control(text: "Barlines", size: (95, 20), position: (2, 160), check_state: Unchecked, parent: apply_to_frame)]
+  #[nwg_events(OnButtonClick: [UI::update_config(SELF)])]
+  pub barline_check: nwg::CheckBox,
+
   //outline around advanced controls
-  #[nwg_control(size: (162, 140), position: (128, 0), parent: options_frame)]
+  #[nwg_control(size: (162, 180), position: (128, 0), parent: options_frame)]
   pub advanced_options_frame: nwg::Frame,
 
   #[nwg_control(text: "Advanced Options:", size: (195, 20), position: (2, 0), parent: advanced_options_frame)]
@@ -248,25 +344,53 @@ pub struct UI {
   #[nwg_events(OnButtonClick: [UI::update_config(SELF), UI::set_flat_scaling(SELF)])]
   pub flat_sv_scale_check: nwg::CheckBox,
 
-  #[nwg_control(text: "", size: (19, 19), position: (2, 117), parent: advanced_options_frame)]
+  //one or more beat divisors, e.g. 1/4 1/3
+  #[nwg_control(text: "", size: (47, 19), position: (2, 117), parent: advanced_options_frame)]
   #[nwg_events(OnTextInput: [UI::update_config(SELF)])]
-  pub snapping_numer_text: nwg::TextInput,
+  pub snapping_text: nwg::TextInput,
 
-  #[nwg_control(text: "/", size: (7, 20), position: (23, 119), parent: advanced_options_frame)]
-  pub snapping_prefix_label: nwg::Label,
+  #[nwg_control(text: "Snapping", size: (100, 20), position: (53, 119), parent: advanced_options_frame)]
+  pub snapping_label: nwg::Label,
 
-  #[nwg_control(text: "", size: (19, 19), position: (30, 117), parent: advanced_options_frame)]
+  //time snappings stop at, empty for the end of the last hit object
+  #[nwg_control(text: "", size: (47, 19), position: (2, 160), parent: advanced_options_frame)]
   #[nwg_events(OnTextInput: [UI::update_config(SELF)])]
-  pub snapping_denom_text: nwg::TextInput,
+  pub snapping_end_text: nwg::TextInput,
 
-  #[nwg_control(text: "Snapping", size: (100, 20), position: (53, 119), parent: advanced_options_frame)]
-  pub snapping_label: nwg::Label,
+  #[nwg_control(text: "Snapping end", size: (100, 20), position: (53, 162), parent: advanced_options_frame)]
+  pub snapping_end_label: nwg::Label,
 
   //toggles end line/start line BPM
   #[nwg_control(text: "Ignore BPM", size: (105, 20), position: (75, 20), check_state: Unchecked, parent: advanced_options_frame)]
   #[nwg_events(OnButtonClick: [UI::update_config(SELF)])]
   pub ign_bpm_check: nwg::CheckBox,
 
+  //toggles rewriting slider lengths so slider ends stay in place
+  #[nwg_control(text: "Keep slider ends", size: (150, 20), position: (2, 140), check_state: Unchecked, parent: advanced_options_frame)]
+  #[nwg_events(OnButtonClick: [UI::update_config(SELF)])]
+  pub fix_sliders_check: nwg::CheckBox,
+
+  //toggles removing redundant inherited lines
+  #[nwg_control(text: "Optimize", size: (95, 18), position: (2, 98), check_state: Unchecked, parent: advanced_options_frame)]
+  #[nwg_events(OnButtonClick: [UI::update_config(SELF)])]
+  pub optimize_check: nwg::CheckBox,
+
+  #[nwg_control(text: "Hit filter:", size: (60, 20), position: (2, 3), parent: filter_frame)]
+  pub hit_filter_label: nwg::Label,
+
+  //filter terms for hitobjects
+  #[nwg_control(text: "", size: (225, 23), position: (65, 1), parent: filter_frame)]
+  #[nwg_events(OnTextInput: [UI::update_config(SELF)])]
+  pub hit_filter_text: nwg::TextInput,
+
+  #[nwg_control(text: "Conflicts:", size: (60, 20), position: (2, 3), parent: merge_frame)]
+  pub merge_policy_label: nwg::Label,
+
+  //how tool points are merged with existing lines within min spacing
+  #[nwg_control(collection: MERGE_POLICIES.to_vec(), selected_index: Some(0), size: (225, 23), position: (65, 1), parent: merge_frame)]
+  #[nwg_events(OnComboxBoxSelection: [UI::update_config(SELF)])]
+  pub merge_policy_combo: nwg::ComboBox<&'static str>,
+
   //select map button
   #[nwg_control(text: "Select Map", size: (87, 25), position: (-1,0), parent: mapselect_frame)]
   #[nwg_events( OnButtonClick: [UI::open_file_browser] )]
@@ -278,7 +402,7 @@ pub struct UI {
 
   //toggles preview
   #[nwg_control(text: "Preview Diff", size: (87, 25), position: (0, 30), check_state: Unchecked, parent: mapselect_frame)]
-  #[nwg_events(OnButtonClick: [UI::fill_out_filename, UI::update_config(SELF)])]
+  #[nwg_events(OnButtonClick: [UI::fill_out_filename, UI::update_config(SELF), UI::update_history_buttons])]
   pub preview_check: nwg::CheckBox,
   
   //output map filename
@@ -287,15 +411,19 @@ pub struct UI {
   pub out_filename: nwg::TextInput,
   
   //place apply button near bottom
-  #[nwg_control(text: "Apply", size: (242, 25), position: (0, 0), flags: "VISIBLE|DISABLED", parent: applyundo_frame)]
+  #[nwg_control(text: "Apply", size: (192, 25), position: (0, 0), flags: "VISIBLE|DISABLED", parent: applyundo_frame)]
   #[nwg_events( OnButtonClick: [UI::apply_changes] )]
   pub apply_button: nwg::Button,
 
   //place undo/redo button near bottom
-  #[nwg_control(text: "Undo", size: (45, 25), position: (247, 0), flags: "VISIBLE|DISABLED", parent: applyundo_frame)]
+  #[nwg_control(text: "Undo", size: (45, 25), position: (197, 0), flags: "VISIBLE|DISABLED", parent: applyundo_frame)]
   #[nwg_events( OnButtonClick: [UI::undo] )]
   pub undo_button: nwg::Button,
 
+  #[nwg_control(text: "Redo", size: (45, 25), position: (247, 0), flags: "VISIBLE|DISABLED", parent: applyundo_frame)]
+  #[nwg_events( OnButtonClick: [UI::redo] )]
+  pub redo_button: nwg::Button,
+
   //place status bar at the very bottom
   #[nwg_control(text: "[map] no map selected (Select Map or drag one in)")]
   pub status: nwg::StatusBar,
@@ -306,6 +434,7 @@ pub struct UI {
 
   pub options: RefCell<AppOptions>, //reference to options which are updated when UI elements are clicked
   pub svt: RefCell<svt::SVT>, //reference to svt which contains logic for tool
+  pub map_fingerprint: RefCell<Option<MapFingerprint>>, //the map as it was when last loaded or written
   pub pos_x: RefCell<i32>,
   pub pos_y: RefCell<i32>,
 }
@@ -323,7 +452,7 @@ impl UI {
 
     //load config and set apply button accordingly
     if self.load_config().is_err() {
-      println!("[load] couldn't load config properly");
+      eprintln!("[load] couldn't load config properly");
       self.apply_button.set_enabled(false);
     }
 
@@ -331,8 +460,8 @@ impl UI {
     self.window.set_size(options.width, options.height);
     self.window.set_position(options.pos_x, options.pos_y);
 
-    //always disable undo button by default
-    self.undo_button.set_enabled(false);
+    //undo/redo follow the history of the loaded map
+    self.update_history_buttons();
 
     self.set_sv_mode(&self.lin_sv_check);
     self.set_flat_scaling();
@@ -342,6 +471,12 @@ impl UI {
   }
 
   fn apply_changes(&self) {
+    //previews don't overwrite the map, anything else shouldn't silently overwrite changes made in the editor
+    let preview = self.preview_check.check_state() == Checked;
+    if !preview && !self.confirm_overwrite("Apply") {
+      return;
+    }
+
     //refresh file before doing anything
     self.load_file();
 
@@ -353,56 +488,121 @@ impl UI {
     //[debug] print out all objects in their current order
     self.svt.borrow().print_debug();
 
+    //parse timing point lines, human-friendly points or editor selections into start/end ranges
     let cmd = self.inherited_text.text();
-    let mut lines = cmd.split_whitespace();
-    let mut start_line;
-    let mut end_line;
-
-    //process 2 valid lines at a time until no lines left
-    loop {
-      start_line = lines.next();
-      end_line = lines.next();
-      if let (Some(start_l), Some(end_l)) = (start_line, end_line) {
-        if let Err(err) = self.svt.borrow_mut().apply_two_point_fn(start_l, end_l, &*self.options.borrow()) {
-          //if error is encountered, stop applying and update status bar
-          println!("[apply] error applying timing {} -> {}", start_l, end_l);
+    let parse_result = self.svt.borrow().parse_input(&cmd);
+    let ranges = match parse_result {
+      Ok(ranges) => ranges,
+      Err(err) => {
+        eprintln!("[apply] error parsing input {:?}", err);
+        self.status.set_text(0, &err.to_string());
+        return;
+      }
+    };
+
+    //process each start/end range in order
+    for range in ranges.iter() {
+      if let Err(err) = self.svt.borrow_mut().apply_two_point_fn(range, &*self.options.borrow()) {
+        //if error is encountered, stop applying and update status bar
+        eprintln!("[apply] error applying timing {} -> {}", range.start.data, range.end.data);
+        self.status.set_text(0, &err.to_string());
+        return;
+      }
+    }
+
+    //snapshot the map before changing it, previews are separate files and aren't part of the history
+    //an unreadable history is started over rather than blocking the change
+    let mut history = None;
+    if !preview {
+      let checkpoint_result = History::load_or_reset(&self.out_filename.text()).and_then(|mut h| h.checkpoint().map(|_| h));
+      match checkpoint_result {
+        Ok(h) => history = Some(h),
+        Err(err) => {
           self.status.set_text(0, &err.to_string());
           return;
         }
-      } else {
-        println!("[apply] no more lines");
-        break;
       }
     }
 
     //merge new points into old ones - delete old point if new one is identical
-    let write_result = self.svt.borrow_mut().write_output_points(self.min_spacing_text.text(), self.in_filename.text(), self.out_filename.text(), self.preview_check.check_state() == Checked);
+    let write_result = self.svt.borrow_mut().write_output_points(self.min_spacing_text.text(), self.merge_policy_combo.selection_string().unwrap_or(String::from(MERGE_POLICIES[0])), self.in_filename.text(), self.out_filename.text(), preview, self.fix_sliders_check.check_state() == Checked, &*self.options.borrow());
     
     if write_result.is_err() {
-      println!("[apply] error writing output");
+      eprintln!("[apply] error writing output");
       self.status.set_text(0, &write_result.unwrap_err().to_string());
       return;
     }
 
     //save config after successful output point write
     if self.save_config().is_err() {
-      println!("[apply] failed to save config file")
+      eprintln!("[apply] failed to save config file")
       //self.status.set_text(0, &format!("[apply] couldn't save config"));
       //return;
     }
 
-    //enable undo button
-    self.undo_button.set_enabled(true);
+    let history_reset = history.as_ref().map_or(false, |h| h.was_reset);
+    if let Some(mut history) = history {
+      if let Err(err) = history.record(&self.apply_label(&ranges)) {
+        eprintln!("[apply] failed to record history {:?}", err);
+      }
+      self.remember_map();
+
+      //the preview is out of date once the changes are in the map itself
+      match preview::remove_preview(&self.in_filename.text()) {
+        Ok(Some(path)) => eprintln!("[preview] removed {}", path.display()),
+        Ok(None) => {},
+        Err(err) => eprintln!("[preview] {:?}", err),
+      }
+    }
+    self.update_history_buttons();
 
     //update status bar with change count on success
-    self.status.set_text(0, &format!("[apply] {} lines applied", write_result.unwrap()));
+    //sliders under changed sv are either rewritten or listed in the apply report
+    let changed_sliders = self.svt.borrow().changed_sliders.len();
+    let mut status_text = format!("[apply] {} lines applied", write_result.unwrap());
+    if self.optimize_check.check_state() == Checked {
+      status_text += &format!(", {} lines saved", self.svt.borrow().optimized_lines);
+    }
+    if history_reset {
+      status_text += ", undo history was unreadable and started over";
+    }
+    if changed_sliders == 0 {
+      self.status.set_text(0, &status_text);
+    } else if self.fix_sliders_check.check_state() == Checked {
+      self.status.set_text(0, &format!("{}, {} sliders rewritten", status_text, changed_sliders));
+    } else {
+      self.status.set_text(0, &format!("{}, {} slider durations changed", status_text, changed_sliders));
+    }
+
+    self.show_apply_report();
+  }
+
+  //what merging did with existing lines and which sliders changed, release builds have no console to read it from
+  fn show_apply_report(&self) {
+    let report = self.svt.borrow().apply_report(self.fix_sliders_check.check_state() == Checked);
+    if report.len() == 0 {
+      return;
+    }
+
+    //long reports are cut short to fit on screen
+    let mut summary: Vec<&str> = report.iter().map(|line| line.as_str()).take(16).collect();
+    if report.len() > 16 {
+      summary.push("...");
+    }
+    let params = nwg::MessageParams{
+      title: "Apply report",
+      content: &summary.join("\n"),
+      buttons: nwg::MessageButtons::Ok,
+      icons: nwg::MessageIcons::Info,
+    };
+    nwg::modal_message(&self.window, &params);
   }
   
   fn close_window(&self) {
     self.update_config();
 
     if self.save_config().is_err() {
-      println!("[close] failed to save config file")
+      eprintln!("[close] failed to save config file")
       //return;
     }
     nwg::stop_thread_dispatch();
@@ -418,27 +618,11 @@ impl UI {
     if self.preview_check.check_state() == Checked {
       let in_filename = &self.in_filename.text();
 
-      //prevent paths without parents or filenames from crashing
-      let path_folder = Path::new(in_filename).parent();
-      let path_osu = Path::new(in_filename).file_name();
-
-      //TODO check path is valid maybe?
-      //TODO fix bracket validation - file without brackets should be cut at .osu instead
-      if let (Some(path_folder), Some(path_osu)) = (path_folder, path_osu) {
-        
-        if let Some(name_osu) = path_osu.to_str() {
-          let preview_cut = if name_osu.contains("[") {
-            name_osu.split("[").nth(0).unwrap_or("")
-          } else {
-            name_osu.split(".").nth(0).unwrap_or("")
-          };
-
-          self.out_filename.set_text(&format!("{}/{}[{}].osu", path_folder.to_str().unwrap_or(""), preview_cut, "preview"));
-        } else {
-          println!("[fof] path name didn't unwrap correctly");
-        }
+      //each difficulty gets its own preview file, so previews of different diffs in a set don't collide
+      if let Some(preview_filename) = preview::preview_filename(in_filename) {
+        self.out_filename.set_text(preview_filename.to_str().unwrap_or(""));
       } else {
-        println!("[fof] issue with either file directory or name: [{}]", in_filename);
+        eprintln!("[fof] issue with either file directory or name: [{}]", in_filename);
         self.status.set_text(0, &format!("[fof] issue with input filename"));
       }
     } else {
@@ -452,7 +636,7 @@ impl UI {
 
     //should never happen
     if filename.len() == 0 {
-      println!("[load] empty filename");
+      eprintln!("[load] empty filename");
       return;
     }
 
@@ -461,7 +645,7 @@ impl UI {
 
     //skip any file that is not .osu
     if ext != Some(OsStr::new("osu")) {
-      println!("[load] invalid file");
+      eprintln!("[load] invalid file");
       self.apply_button.set_enabled(false);
       self.status.set_text(0, &format!("[load] please select a .osu file"));
       return;
@@ -473,9 +657,10 @@ impl UI {
     let path_osu = Path::new(&filename).file_name();
 
     if let (Some(path_folder), Some(path_osu)) = (path_folder, path_osu) {
-      println!("[load] folder: {}", path_folder.to_str().unwrap_or("folder_dne"));
-      println!("[load] file: {}", path_osu.to_str().unwrap_or("filename_dne.osu"));
-      println!("[load] load starting");
+      eprintln!("[load] folder: {}", path_folder.to_str().unwrap_or("folder_dne"));
+      eprintln!("[load] file: {}", path_osu.to_str().unwrap_or("filename_dne.osu"));
+      eprintln!("[load] load starting");
+      self.remember_map();
       let load_result = self.svt.borrow_mut().load_osu(&filename, &*self.options.borrow());
       if load_result.is_err() {
         self.status.set_text(0, &load_result.unwrap_err().to_string());
@@ -483,12 +668,19 @@ impl UI {
       }
 
       if self.save_config().is_err() {
-        println!("[load] failed to save config file")
+        eprintln!("[load] failed to save config file")
         //self.status.set_text(0, &format!("[apply] couldn't save config"));
         //return;
       }
       self.apply_button.set_enabled(true);
-      self.status.set_text(0, &format!("editing {}", path_osu.to_str().unwrap_or("filename_dne.osu")));
+      self.update_history_buttons();
+      //malformed lines are left out of the map svt works with, so they're worth knowing about before applying
+      let skipped_lines = self.svt.borrow().skipped_lines;
+      let mut status_text = format!("editing {}", path_osu.to_str().unwrap_or("filename_dne.osu"));
+      if skipped_lines > 0 {
+        status_text += &format!(", {} malformed lines skipped", skipped_lines);
+      }
+      self.status.set_text(0, &status_text);
     } else {
       self.status.set_text(0, &format!("[load] issue with either file directory or name"));
     }
@@ -517,24 +709,120 @@ impl UI {
     }
   }
 
-  //assumes a map is loaded and a change has been applied already
+  //restore the map to the state before the last change in its history
   fn undo(&self) {
-    if let Err(e) = libxch::xch(self.in_filename.text(), "backup.osu") {
-      self.status.set_text(0, &format!("[undo] failed {}", e.to_string()));
+    if !self.confirm_overwrite("Undo") {
+      return;
+    }
+    match History::load(&self.out_filename.text()).and_then(|mut history| history.undo()) {
+      Ok(label) => {
+        self.load_file();
+        self.status.set_text(0, &format!("[undo] undid {}", label));
+      },
+      Err(err) => self.status.set_text(0, &err.to_string()),
+    }
+    self.update_history_buttons();
+  }
+
+  //reapply the last undone change
+  fn redo(&self) {
+    if !self.confirm_overwrite("Redo") {
+      return;
+    }
+    match History::load(&self.out_filename.text()).and_then(|mut history| history.redo()) {
+      Ok(label) => {
+        self.load_file();
+        self.status.set_text(0, &format!("[redo] redid {}", label));
+      },
+      Err(err) => self.status.set_text(0, &err.to_string()),
+    }
+    self.update_history_buttons();
+  }
+
+  //fingerprint the map as it is now, as the base for the next operation
+  fn remember_map(&self) {
+    match MapFingerprint::read(&self.in_filename.text()) {
+      Ok(fingerprint) => { self.map_fingerprint.replace(Some(fingerprint)); },
+      Err(err) => {
+        eprintln!("[check] {:?}", err);
+        self.map_fingerprint.replace(None);
+      },
+    }
+  }
+
+  //whether the map can be overwritten, asking first if it changed on disk since svt last loaded or wrote it
+  fn confirm_overwrite(&self, action: &str) -> bool {
+    let fingerprint = match &*self.map_fingerprint.borrow() {
+      Some(fingerprint) if fingerprint.path == self.in_filename.text() => fingerprint.clone(),
+      _ => return true,
+    };
+
+    let current = match fingerprint.changed() {
+      Ok(None) => return true,
+      Ok(Some(current)) => current,
+      Err(err) => {
+        self.status.set_text(0, &err.to_string());
+        return false;
+      },
+    };
+
+    let changes = fingerprint.describe_changes(&current);
+    eprintln!("[check] map changed on disk since it was loaded\n{}", changes);
+
+    //long change lists are cut short, the full list is in the console
+    let mut summary: Vec<&str> = changes.lines().take(12).collect();
+    if changes.lines().count() > 12 {
+      summary.push("...");
     }
-    //fs::copy(self.in_filename.text(), "temp.osu")?;
-    //fs::copy("backup.osu", self.in_filename.text())?;
-    //fs::copy("temp.osu", "backup.osu")?;
-    //fs::remove_file("temp.osu")?;
+    let params = nwg::MessageParams{
+      title: "Map changed on disk",
+      content: &format!("The map was changed outside svt since it was loaded:\n\n{}\n\n{} anyway?", summary.join("\n"), action),
+      buttons: nwg::MessageButtons::YesNo,
+      icons: nwg::MessageIcons::Warning,
+    };
+    match nwg::modal_message(&self.window, &params) {
+      nwg::MessageChoice::Yes => true,
+      _ => {
+        self.status.set_text(0, &format!("[check] map changed on disk, reopen it or confirm to overwrite"));
+        false
+      },
+    }
+  }
 
-    self.undo_button.set_enabled(false);
-    self.status.set_text(0, &format!("[undo] successful"));
+  fn update_history_buttons(&self) {
+    let history = History::load(&self.out_filename.text()).unwrap_or_default();
+    self.undo_button.set_enabled(history.can_undo());
+    self.redo_button.set_enabled(history.can_redo());
+  }
+
+  //history label for an apply, e.g. lin. sv + vol 00:01:000-00:05:000
+  fn apply_label(&self, ranges: &[InputRange]) -> String {
+    let options = self.options.borrow();
+    let mut modes = Vec::new();
+    if ranges.iter().any(|range| range.clear.is_some()) { modes.push("clear"); }
+    if ranges.iter().any(|range| range.clear.is_none()) {
+      if options.lin_sv { modes.push("lin. sv"); }
+      if options.exp_sv { modes.push("exp. sv"); }
+      if options.pol_sv { modes.push("pol. sv"); }
+      if options.sin_sv { modes.push("sin. sv"); }
+      if options.flat_sv { modes.push("flat sv"); }
+      if options.vol { modes.push("vol"); }
+    }
+    if options.optimize { modes.push("optimize"); }
+
+    let mut label = modes.join(" + ");
+    if let Some(range) = ranges.first() {
+      label += &format!(" {}-{}", svt::format_timestamp(range.start.time), svt::format_timestamp(range.end.time));
+    }
+    if ranges.len() > 1 {
+      label += &format!(" and {} more", ranges.len() - 1);
+    }
+    label
   }
 
   fn load_config(&self) -> Result<()> {
     // read file
-    let app_options_string = fs::read_to_string(SVT_OPTIONS_FILE).unwrap_or(String::from(""));
-    let mut app_options = serde_json::from_str(&app_options_string).unwrap_or(AppOptions{..Default::default()});
+    let mut app_options = read_options();
 
     self.inherited_text.set_text(&app_options.inh_times);
     self.in_filename.set_text(&app_options.map);
@@ -547,6 +835,13 @@ impl UI {
     self.hit_check.set_check_state(if app_options.hits {Checked} else {Unchecked});
     self.snapping_check.set_check_state(if app_options.snappings {Checked} else {Unchecked});
     self.inh_check.set_check_state(if app_options.inh_lines {Checked} else {Unchecked});
+    self.repeat_check.set_check_state(if app_options.slider_repeats {Checked} else {Unchecked});
+    self.tail_check.set_check_state(if app_options.slider_tails {Checked} else {Unchecked});
+    self.tick_check.set_check_state(if app_options.slider_ticks {Checked} else {Unchecked});
+    self.spinner_end_check.set_check_state(if app_options.spinner_ends {Checked} else {Unchecked});
+    self.barline_check.set_check_state(if app_options.barlines {Checked} else {Unchecked});
+    self.hit_filter_text.set_text(&app_options.hit_filter);
+    self.merge_policy_combo.set_selection(Some(MERGE_POLICIES.iter().position(|p| *p == app_options.merge_policy).unwrap_or(0)));
     self.offset_text.set_text(&app_options.offset);
     self.buffer_text.set_text(&app_options.buffer);
     self.min_spacing_text.set_text(&app_options.min_spacing);
@@ -554,9 +849,11 @@ impl UI {
     self.flat_sv_scale_check.set_check_state(if app_options.flat_scaling {Checked} else {Unchecked});
     self.flat_sv_text.set_text(&app_options.flat_change);
     self.flat_sv_scale_text.set_text(&app_options.flat_scaling_change);
-    self.snapping_numer_text.set_text(&app_options.snapping_numer);
-    self.snapping_denom_text.set_text(&app_options.snapping_denom);
+    self.snapping_text.set_text(&app_options.snapping);
+    self.snapping_end_text.set_text(&app_options.snapping_end);
     self.ign_bpm_check.set_check_state(if app_options.ignore_bpm {Checked} else {Unchecked});
+    self.fix_sliders_check.set_check_state(if app_options.fix_sliders {Checked} else {Unchecked});
+    self.optimize_check.set_check_state(if app_options.optimize {Checked} else {Unchecked});
 
     //validation on x/y
     if app_options.pos_x < 0 || app_options.pos_x > nwg::Monitor::width() - 300 {
@@ -588,15 +885,6 @@ impl UI {
     let (x,y) = self.window.position();
     let (w,h) = self.window.size();
 
-    //err validation on snap numer and denom
-    if !self.snapping_numer_text.text().parse::<i32>().unwrap_or(0).is_positive() {
-      self.snapping_numer_text.set_text("1");
-    }
-
-    if !self.snapping_denom_text.text().parse::<i32>().unwrap_or(0).is_positive() {
-      self.snapping_denom_text.set_text("1");
-    }
-
     let app_options = AppOptions{
       map: self.in_filename.text(),
       inh_times: self.inherited_text.text(),
@@ -609,6 +897,13 @@ impl UI {
       hits: self.hit_check.check_state() == Checked,
       snappings: self.snapping_check.check_state() == Checked,
       inh_lines: self.inh_check.check_state() == Checked,
+      slider_repeats: self.repeat_check.check_state() == Checked,
+      slider_tails: self.tail_check.check_state() == Checked,
+      slider_ticks: self.tick_check.check_state() == Checked,
+      spinner_ends: self.spinner_end_check.check_state() == Checked,
+      barlines: self.barline_check.check_state() == Checked,
+      hit_filter: self.hit_filter_text.text(),
+      merge_policy: self.merge_policy_combo.selection_string().unwrap_or(String::from(MERGE_POLICIES[0])),
       offset: self.offset_text.text(),
       buffer: self.buffer_text.text(),
       min_spacing: self.min_spacing_text.text(),
@@ -616,14 +911,22 @@ impl UI {
       flat_scaling: self.flat_sv_scale_check.check_state() == Checked,
       flat_change: self.flat_sv_text.text(),
       flat_scaling_change: self.flat_sv_scale_text.text(),
-      snapping_numer: self.snapping_numer_text.text(),
-      snapping_denom: self.snapping_denom_text.text(),
+      snapping: self.snapping_text.text(),
+      snapping_end: self.snapping_end_text.text(),
       ignore_bpm: self.ign_bpm_check.check_state() == Checked,
+      fix_sliders: self.fix_sliders_check.check_state() == Checked,
+      optimize: self.optimize_check.check_state() == Checked,
       pos_x: x,
       pos_y: y,
       width: w,
       height: h,
       tooltips: self.options.borrow().tooltips,
+      //backup settings, precision and format upgrades are only set in the config file
+      backup_folder: self.options.borrow().backup_folder.clone(),
+      backup_count: self.options.borrow().backup_count,
+      backup_days: self.options.borrow().backup_days,
+      precision: self.options.borrow().precision,
+      upgrade_timing_points: self.options.borrow().upgrade_timing_points,
       experimental: String::from(""),
     };
 
@@ -665,8 +968,8 @@ impl UI {
       w_new = 300;
     }
 
-    if h < 350 {
-      h_new = 350;
+    if h < DEFAULT_WINDOW_HEIGHT {
+      h_new = DEFAULT_WINDOW_HEIGHT;
     }
 
     if (w_new,h_new) != (w,h) {
@@ -724,8 +1027,14 @@ impl UI {
     if self.flat_sv_check.check_state() == Checked {
       self.flat_sv_scale_check.set_visible(true);
       self.hit_check.set_enabled(false);
+      self.hit_filter_text.set_enabled(false);
       self.snapping_check.set_enabled(false);
       self.inh_check.set_enabled(false);
+      self.repeat_check.set_enabled(false);
+      self.tail_check.set_enabled(false);
+      self.tick_check.set_enabled(false);
+      self.spinner_end_check.set_enabled(false);
+      self.barline_check.set_enabled(false);
       self.ign_bpm_check.set_enabled(false);
 
       //set visiblity of all flat scaling advanced options
@@ -741,8 +1050,14 @@ impl UI {
       self.flat_sv_scale_label.set_visible(false);
 
       self.hit_check.set_enabled(true);
+      self.hit_filter_text.set_enabled(true);
       self.snapping_check.set_enabled(true);
       self.inh_check.set_enabled(true);
+      self.repeat_check.set_enabled(true);
+      self.tail_check.set_enabled(true);
+      self.tick_check.set_enabled(true);
+      self.spinner_end_check.set_enabled(true);
+      self.barline_check.set_enabled(true);
       self.ign_bpm_check.set_enabled(true);
     }
 
@@ -764,9 +1079,9 @@ impl UI {
 
   fn set_snapping(&self) {
     let snapping_enabled = self.snapping_check.check_state() == Checked;
-    self.snapping_numer_text.set_visible(snapping_enabled);
-    self.snapping_denom_text.set_visible(snapping_enabled);
-    self.snapping_prefix_label.set_visible(snapping_enabled);
+    self.snapping_text.set_visible(snapping_enabled);
     self.snapping_label.set_visible(snapping_enabled);
+    self.snapping_end_text.set_visible(snapping_enabled);
+    self.snapping_end_label.set_visible(snapping_enabled);
   }
 }
\ No newline at end of file